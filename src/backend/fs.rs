@@ -0,0 +1,198 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::Backend;
+use crate::error::Error;
+
+/// FsBackend stores each object as a file named after its CID in a local
+/// directory. References are kept as files in its `refs` subdirectory.
+#[derive(Clone, Debug)]
+pub struct FsBackend {
+    path: PathBuf,
+}
+
+impl FsBackend {
+    /// Opens the backend at `path`, creating the directory if needed.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        if let Err(e) = fs::create_dir_all(&path) {
+            return Err(Error::Backend(format!(
+                "Error creating backend directory {}: {}",
+                path.display(),
+                e
+            )));
+        }
+
+        Ok(FsBackend { path })
+    }
+
+    fn object_path(&self, cid: &str) -> Result<PathBuf, Error> {
+        // CIDs are multibase encoded, so anything else could escape the
+        // backend directory
+        if !is_valid_name(cid) {
+            return Err(Error::Backend(format!("Invalid object CID: {:?}", cid)));
+        }
+
+        Ok(self.path.join(cid))
+    }
+
+    fn ref_path(&self, name: &str) -> Result<PathBuf, Error> {
+        if !is_valid_name(name) {
+            return Err(Error::Backend(format!(
+                "Invalid reference name: {:?}",
+                name
+            )));
+        }
+
+        Ok(self.path.join("refs").join(name))
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+impl Backend for FsBackend {
+    fn put(&mut self, cid: &str, data: &[u8]) -> Result<(), Error> {
+        let path = self.object_path(cid)?;
+        if path.exists() {
+            return Ok(());
+        }
+
+        // writes to a temporary file first, so that a partially written
+        // object is never visible under its CID
+        let tmp_path = self.path.join(format!(".{}.tmp", cid));
+        fs::write(&tmp_path, data).map_err(backend_error)?;
+        fs::rename(&tmp_path, &path).map_err(backend_error)
+    }
+
+    fn get(&self, cid: &str) -> Result<Option<Vec<u8>>, Error> {
+        match fs::read(self.object_path(cid)?) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(backend_error(e)),
+        }
+    }
+
+    fn has(&self, cid: &str) -> Result<bool, Error> {
+        Ok(self.object_path(cid)?.is_file())
+    }
+
+    fn delete(&mut self, cid: &str) -> Result<(), Error> {
+        match fs::remove_file(self.object_path(cid)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(backend_error(e)),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<String>, Error> {
+        let entries = fs::read_dir(&self.path).map_err(backend_error)?;
+
+        let mut cids = vec![];
+        for entry in entries {
+            let entry = entry.map_err(backend_error)?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || !entry.path().is_file() {
+                continue;
+            }
+            cids.push(name);
+        }
+        cids.sort();
+
+        Ok(cids)
+    }
+
+    fn set_ref(&mut self, name: &str, cid: Option<&str>) -> Result<(), Error> {
+        let path = self.ref_path(name)?;
+        let cid = match cid {
+            Some(cid) => cid,
+            None => {
+                return match fs::remove_file(&path) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => Err(backend_error(e)),
+                    _ => Ok(()),
+                }
+            }
+        };
+
+        let refs_dir = self.path.join("refs");
+        fs::create_dir_all(&refs_dir).map_err(backend_error)?;
+        let tmp_path = refs_dir.join(format!(".{}.tmp", name));
+        fs::write(&tmp_path, cid).map_err(backend_error)?;
+        fs::rename(&tmp_path, &path).map_err(backend_error)
+    }
+
+    fn get_ref(&self, name: &str) -> Result<Option<String>, Error> {
+        match fs::read_to_string(self.ref_path(name)?) {
+            Ok(cid) => Ok(Some(cid)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(backend_error(e)),
+        }
+    }
+}
+
+fn backend_error(e: std::io::Error) -> Error {
+    Error::Backend(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_get_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut backend = FsBackend::new(dir.path().join("store")).unwrap();
+        assert_eq!(backend.get("cid1").unwrap(), None);
+        assert_eq!(backend.list().unwrap(), Vec::<String>::new());
+
+        backend.put("cid1", &[1, 2, 3]).unwrap();
+        backend.put("cid2", &[4, 5, 6]).unwrap();
+
+        assert!(backend.has("cid1").unwrap());
+        assert_eq!(backend.get("cid1").unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(backend.list().unwrap(), vec!["cid1", "cid2"]);
+
+        // objects are persisted across backend instances
+        let mut backend = FsBackend::new(dir.path().join("store")).unwrap();
+        assert_eq!(backend.get("cid2").unwrap(), Some(vec![4, 5, 6]));
+
+        backend.delete("cid1").unwrap();
+        backend.delete("cid1").unwrap();
+        assert!(!backend.has("cid1").unwrap());
+        assert_eq!(backend.list().unwrap(), vec!["cid2"]);
+    }
+
+    #[test]
+    fn refs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut backend = FsBackend::new(dir.path()).unwrap();
+        assert_eq!(backend.get_ref("index").unwrap(), None);
+
+        backend.set_ref("index", Some("cid1")).unwrap();
+        backend.set_ref("index", Some("cid2")).unwrap();
+        let backend_reopened = FsBackend::new(dir.path()).unwrap();
+        assert_eq!(
+            backend_reopened.get_ref("index").unwrap(),
+            Some("cid2".to_string())
+        );
+        // references are not listed as objects
+        assert_eq!(backend.list().unwrap(), Vec::<String>::new());
+
+        backend.set_ref("index", None).unwrap();
+        backend.set_ref("index", None).unwrap();
+        assert_eq!(backend.get_ref("index").unwrap(), None);
+        assert!(backend.set_ref("../index", Some("cid1")).is_err());
+    }
+
+    #[test]
+    fn invalid_cid() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut backend = FsBackend::new(dir.path()).unwrap();
+
+        assert!(backend.put("../escape", &[1]).is_err());
+        assert!(backend.get("a/b").is_err());
+        assert!(backend.has("").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use super::Backend;
+use crate::error::Error;
+
+/// MemoryBackend keeps all objects in memory. It is mostly useful for tests and
+/// for short lived processes which do not need to persist data.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBackend {
+    objects: BTreeMap<String, Vec<u8>>,
+    refs: BTreeMap<String, String>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        MemoryBackend::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn put(&mut self, cid: &str, data: &[u8]) -> Result<(), Error> {
+        self.objects.insert(cid.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, cid: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.objects.get(cid).cloned())
+    }
+
+    fn has(&self, cid: &str) -> Result<bool, Error> {
+        Ok(self.objects.contains_key(cid))
+    }
+
+    fn delete(&mut self, cid: &str) -> Result<(), Error> {
+        self.objects.remove(cid);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>, Error> {
+        Ok(self.objects.keys().cloned().collect())
+    }
+
+    fn set_ref(&mut self, name: &str, cid: Option<&str>) -> Result<(), Error> {
+        match cid {
+            Some(cid) => self.refs.insert(name.to_string(), cid.to_string()),
+            None => self.refs.remove(name),
+        };
+        Ok(())
+    }
+
+    fn get_ref(&self, name: &str) -> Result<Option<String>, Error> {
+        Ok(self.refs.get(name).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_get_delete() {
+        let mut backend = MemoryBackend::new();
+        assert!(backend.is_empty());
+        assert_eq!(backend.get("cid1").unwrap(), None);
+
+        backend.put("cid1", &[1, 2, 3]).unwrap();
+        backend.put("cid2", &[4, 5, 6]).unwrap();
+
+        assert!(backend.has("cid1").unwrap());
+        assert_eq!(backend.get("cid1").unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(backend.list().unwrap(), vec!["cid1", "cid2"]);
+
+        backend.delete("cid1").unwrap();
+        backend.delete("cid1").unwrap();
+        assert!(!backend.has("cid1").unwrap());
+        assert_eq!(backend.len(), 1);
+    }
+
+    #[test]
+    fn refs() {
+        let mut backend = MemoryBackend::new();
+        assert_eq!(backend.get_ref("index").unwrap(), None);
+
+        backend.set_ref("index", Some("cid1")).unwrap();
+        backend.set_ref("index", Some("cid2")).unwrap();
+        assert_eq!(backend.get_ref("index").unwrap(), Some("cid2".to_string()));
+        // references are not objects
+        assert!(backend.is_empty());
+
+        backend.set_ref("index", None).unwrap();
+        assert_eq!(backend.get_ref("index").unwrap(), None);
+    }
+}
@@ -0,0 +1,108 @@
+use super::Backend;
+use crate::error::Error;
+
+/// MirrorBackend replicates objects across several backends. Writes and deletes
+/// go to every backend, while reads are served by the first backend which has
+/// the object.
+pub struct MirrorBackend {
+    backends: Vec<Box<dyn Backend>>,
+}
+
+impl MirrorBackend {
+    pub fn new(backends: Vec<Box<dyn Backend>>) -> Self {
+        MirrorBackend { backends }
+    }
+}
+
+impl Backend for MirrorBackend {
+    fn put(&mut self, cid: &str, data: &[u8]) -> Result<(), Error> {
+        for backend in self.backends.iter_mut() {
+            backend.put(cid, data)?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, cid: &str) -> Result<Option<Vec<u8>>, Error> {
+        for backend in &self.backends {
+            if let Some(data) = backend.get(cid)? {
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+
+    fn has(&self, cid: &str) -> Result<bool, Error> {
+        for backend in &self.backends {
+            if backend.has(cid)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn delete(&mut self, cid: &str) -> Result<(), Error> {
+        for backend in self.backends.iter_mut() {
+            backend.delete(cid)?;
+        }
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>, Error> {
+        let mut cids = vec![];
+        for backend in &self.backends {
+            cids.append(&mut backend.list()?);
+        }
+        cids.sort();
+        cids.dedup();
+
+        Ok(cids)
+    }
+
+    fn set_ref(&mut self, name: &str, cid: Option<&str>) -> Result<(), Error> {
+        for backend in self.backends.iter_mut() {
+            backend.set_ref(name, cid)?;
+        }
+        Ok(())
+    }
+
+    fn get_ref(&self, name: &str) -> Result<Option<String>, Error> {
+        for backend in &self.backends {
+            if let Some(cid) = backend.get_ref(name)? {
+                return Ok(Some(cid));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    #[test]
+    fn mirror() {
+        let mut first = MemoryBackend::new();
+        first.put("cid1", &[1]).unwrap();
+        let mut second = MemoryBackend::new();
+        second.put("cid2", &[2]).unwrap();
+
+        let mut mirror = MirrorBackend::new(vec![Box::new(first), Box::new(second)]);
+        assert_eq!(mirror.get("cid2").unwrap(), Some(vec![2]));
+        assert_eq!(mirror.list().unwrap(), vec!["cid1", "cid2"]);
+
+        mirror.put("cid3", &[3]).unwrap();
+        for backend in &mirror.backends {
+            assert!(backend.has("cid3").unwrap());
+        }
+
+        mirror.delete("cid1").unwrap();
+        assert!(!mirror.has("cid1").unwrap());
+        assert_eq!(mirror.list().unwrap(), vec!["cid2", "cid3"]);
+
+        mirror.set_ref("index", Some("cid3")).unwrap();
+        for backend in &mirror.backends {
+            assert_eq!(backend.get_ref("index").unwrap(), Some("cid3".to_string()));
+        }
+    }
+}
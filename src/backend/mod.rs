@@ -0,0 +1,39 @@
+mod fs;
+mod memory;
+mod mirror;
+
+pub use fs::FsBackend;
+pub use memory::MemoryBackend;
+pub use mirror::MirrorBackend;
+
+use crate::error::Error;
+
+/// Trait Backend abstracts the storage where encrypted objects are kept. Objects
+/// are opaque byte blobs addressed by their CID, so a backend does not need to
+/// understand the en-crypt data structures to store them. Named references
+/// point to objects whose CID changes as they are updated, such as the tag
+/// index.
+pub trait Backend {
+    /// Stores `data` under `cid`. Storing an object which already exists is a
+    /// no-op, since the same CID always refers to the same content.
+    fn put(&mut self, cid: &str, data: &[u8]) -> Result<(), Error>;
+
+    /// Returns the object stored under `cid`, or `None` if it does not exist.
+    fn get(&self, cid: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Returns whether an object is stored under `cid`.
+    fn has(&self, cid: &str) -> Result<bool, Error>;
+
+    /// Removes the object stored under `cid`. Deleting an object which does
+    /// not exist is not an error.
+    fn delete(&mut self, cid: &str) -> Result<(), Error>;
+
+    /// Lists the CIDs of all objects in the backend.
+    fn list(&self) -> Result<Vec<String>, Error>;
+
+    /// Points the reference `name` to `cid`, or removes it if `cid` is `None`.
+    fn set_ref(&mut self, name: &str, cid: Option<&str>) -> Result<(), Error>;
+
+    /// Returns the CID the reference `name` points to, if set.
+    fn get_ref(&self, name: &str) -> Result<Option<String>, Error>;
+}
@@ -1,94 +1,1008 @@
-use serde_cbor::de;
-use std::fs::File;
-use std::io::Read;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::structs::Pointer;
+use en_crypt::{
+    tag_tokens_key, Backend, Error, Identity, Key, Match, Metadata, Pointer, PointerBuilder, Query,
+    Recipient, TagIndex, Tags,
+};
 
-pub fn handle_store_command(file_path: &str, key: &str) -> Vec<u8> {
-    // opens and read input file
-    let mut f = match File::open(file_path) {
-        Ok(f) => f,
-        Err(err) => {
-            println!("Error opening file: {:?}", err);
-            std::process::exit(0);
+use crate::config::Config;
+
+/// Secret a stored file is decrypted with.
+pub enum Secret {
+    /// Passphrase the file key is derived from
+    Passphrase(String),
+    /// Identity of a recipient the file was encrypted to
+    Identity(Identity),
+}
+
+impl Secret {
+    /// Returns the key of the pointer `header`.
+    fn pointer_key(&self, header: &Pointer) -> Result<Key, Error> {
+        match self {
+            Secret::Passphrase(passphrase) => header.derive_key(passphrase),
+            Secret::Identity(identity) => header.unwrap_key(identity),
         }
-    };
-    let mut buf: Vec<u8> = Vec::new();
-    if let Err(err) = f.read_to_end(&mut buf) {
-        println!("Error reading file: {:?}", err);
-        std::process::exit(0);
-    };
+    }
+}
 
-    let pointer = match Pointer::from(&buf) {
-        Ok(p) => p,
-        Err(err) => {
-            println!("Error transforming file: {:?}", err);
-            std::process::exit(0);
+/// Encrypts and stores a file together with its metadata, returning the root
+/// CID of the stored pointer. The file is read and stored one block at a time.
+/// If `file_path` is "-", the data is read from stdin. The file is encrypted
+/// to `recipients` if any, or else with a key derived from the passphrase
+/// `key`, but not both. If a search passphrase is set, the pointer is made
+/// searchable by its tags and added to the tag index.
+pub fn handle_store_command(
+    file_path: &str,
+    key: Option<&str>,
+    recipients: &[Recipient],
+    search_key: Option<&str>,
+    tags: Vec<String>,
+    config: &Config,
+) -> Result<String, Error> {
+    match (key, recipients.is_empty()) {
+        (Some(_), false) => {
+            return Err(Error::InvalidArgument(
+                "Key (-k) and recipient (-r) params cannot be used together".to_string(),
+            ))
         }
+        (None, true) => {
+            return Err(Error::InvalidArgument(
+                "Key (-k) or recipient (-r) param not provided".to_string(),
+            ))
+        }
+        _ => {}
+    }
+
+    // opens input file
+    let mut input: Box<dyn Read> = if file_path == "-" {
+        Box::new(std::io::stdin())
+    } else {
+        Box::new(File::open(file_path)?)
     };
 
-    // refactor
-    let mut buf_key: [u8; 32] = [0; 32];
-    let mut i = 0;
-    for b in key.as_bytes() {
-        buf_key[i] = *b;
-        i = i + 1;
+    let mut backend = config.open_backend()?;
+    let mut builder = match key {
+        Some(key) => PointerBuilder::with_passphrase(
+            key,
+            config.kdf.params(),
+            config.chunker(),
+            backend.as_mut(),
+        )?,
+        None => PointerBuilder::for_recipients(recipients, config.chunker(), backend.as_mut())?,
+    };
+    builder = builder.cipher(config.cipher);
+    if config.convergent {
+        builder = builder.convergent();
     }
-    let encrypted_pointer = match pointer.encrypt(&buf_key) {
-        Ok(p) => p,
-        Err(err) => {
-            println!("Error encrypting file: {:?}", err);
-            std::process::exit(0);
+    let tags = Tags { values: tags };
+    let metadata = if file_path == "-" {
+        Metadata::for_file(Path::new(""), tags)?
+    } else {
+        Metadata::for_file(Path::new(file_path), tags)?
+    };
+    let search_key = match search_key {
+        Some(search_key) => Some(derive_search_key(search_key, config)?),
+        None => None,
+    };
+    let tokens = match &search_key {
+        Some(search_key) => {
+            let tokens_key = tag_tokens_key(search_key);
+            builder = builder.searchable(&tokens_key);
+            metadata.tag_tokens(&tokens_key)?
         }
+        None => vec![],
     };
+    builder = builder.metadata(metadata);
+
+    std::io::copy(&mut input, &mut builder)?;
+    let root = builder.finish()?;
 
-    serde_cbor::to_vec(&encrypted_pointer).unwrap()
+    if let Some(search_key) = search_key {
+        let mut index = TagIndex::load(backend.as_ref(), &search_key)?.unwrap_or_default();
+        index.insert(&root, &tokens);
+        index.save(backend.as_mut(), &search_key)?;
+    }
+
+    Ok(root)
 }
 
-pub fn handle_read_command(file_path: &str, key: &str) -> Vec<u8> {
-    // opens and read input file
-    let mut f = match File::open(file_path) {
-        Ok(f) => f,
-        Err(err) => {
-            println!("Error opening file: {:?}", err);
-            std::process::exit(0);
+/// Deletes the file stored under `cid` and its blocks. If a search passphrase
+/// is set, the file is also removed from the tag index.
+pub fn handle_delete_command(
+    cid: &str,
+    secret: &Secret,
+    search_key: Option<&str>,
+    config: &Config,
+) -> Result<(), Error> {
+    let mut backend = config.open_backend()?;
+
+    let header = Pointer::load_header(cid, backend.as_ref())?;
+    let derived_key = secret.pointer_key(&header)?;
+    Pointer::delete(cid, &derived_key, backend.as_mut())?;
+
+    if let Some(search_key) = search_key {
+        let search_key = derive_search_key(search_key, config)?;
+        if let Some(mut index) = TagIndex::load(backend.as_ref(), &search_key)? {
+            if index.remove(cid) {
+                index.save(backend.as_mut(), &search_key)?;
+            }
         }
-    };
-    let mut buf: Vec<u8> = Vec::new();
-    if let Err(err) = f.read_to_end(&mut buf) {
-        println!("Error reading file: {:?}", err);
-        std::process::exit(0);
-    };
+    }
+
+    Ok(())
+}
+
+/// Files rekeyed, as their old and new root CIDs, and the number of files
+/// skipped because they were stored with an earlier format.
+#[derive(Debug, PartialEq)]
+pub struct Rekeyed {
+    pub cids: Vec<(String, String)>,
+    pub unsupported: usize,
+}
+
+/// Changes the passphrase of every file stored with the passphrase `key` to
+/// `new_key`. Only the data key wrapped in each pointer header is encrypted
+/// again, so the blocks are kept as they are. Since the header is content
+/// addressed, each file gets a new root CID and the old header is deleted. If
+/// a search passphrase is set, the tag index is updated with the new CIDs.
+/// Returns the old and new root CIDs of the files rekeyed.
+pub fn handle_rekey_command(
+    key: &str,
+    new_key: &str,
+    search_key: Option<&str>,
+    config: &Config,
+) -> Result<Rekeyed, Error> {
+    let mut backend = config.open_backend()?;
+
+    let mut rekeyed = vec![];
+    let (pointers, unsupported) = encrypted_pointers(backend.as_ref())?;
+    for (cid, header) in pointers {
+        let mut pointer = match header.rekey(key, new_key, config.kdf.params()) {
+            Ok(pointer) => pointer,
+            // stored with another passphrase
+            Err(Error::WrongKey) => continue,
+            Err(e) => return Err(e),
+        };
+        let new_cid = pointer.store(backend.as_mut())?;
+        rekeyed.push((cid, new_cid));
+    }
+
+    if let Some(search_key) = search_key {
+        let search_key = derive_search_key(search_key, config)?;
+        if let Some(mut index) = TagIndex::load(backend.as_ref(), &search_key)? {
+            let mut changed = false;
+            for (cid, new_cid) in &rekeyed {
+                changed |= index.rename(cid, new_cid);
+            }
+            if changed {
+                index.save(backend.as_mut(), &search_key)?;
+            }
+        }
+    }
+
+    // the old headers are only deleted once the new ones are stored and indexed
+    for (cid, _) in &rekeyed {
+        backend.delete(cid)?;
+    }
+
+    Ok(Rekeyed {
+        cids: rekeyed,
+        unsupported,
+    })
+}
+
+/// Decrypts the file stored under `cid` and writes it to `out`, one block at a
+/// time.
+pub fn handle_read_command(
+    cid: &str,
+    secret: &Secret,
+    config: &Config,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let backend = config.open_backend()?;
+
+    let encrypted_pointer = Pointer::load_header(cid, backend.as_ref())?;
+    let derived_key = secret.pointer_key(&encrypted_pointer)?;
+    let mut reader = encrypted_pointer.reader(&derived_key, backend.as_ref())?;
+
+    std::io::copy(&mut reader, out)?;
+    Ok(())
+}
+
+/// Stores again, with the passphrase `key`, a file whose pointer was written by
+/// the first version of en-crypt to `file_path`, and returns its root CID. The
+/// pointer is read either as raw CBOR or as the list of bytes that version
+/// printed.
+pub fn handle_migrate_command(
+    file_path: &str,
+    key: &str,
+    config: &Config,
+) -> Result<String, Error> {
+    let raw = std::fs::read(file_path)?;
+    let data = Pointer::decrypt_legacy(&printed_bytes(&raw).unwrap_or(raw), key)?;
+
+    let mut backend = config.open_backend()?;
+    let mut builder = PointerBuilder::with_passphrase(
+        key,
+        config.kdf.params(),
+        config.chunker(),
+        backend.as_mut(),
+    )?
+    .cipher(config.cipher);
+    if config.convergent {
+        builder = builder.convergent();
+    }
+    builder.write_all(&data)?;
+    builder.finish()
+}
+
+/// Parses bytes printed as a list, such as "[1, 2, 3]".
+fn printed_bytes(raw: &[u8]) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(raw).ok()?.trim();
+    let list = text.strip_prefix('[')?.strip_suffix(']')?;
+    list.split(',').map(|b| b.trim().parse().ok()).collect()
+}
+
+/// Generates a new identity and writes it to the file `output`, which must not
+/// exist yet, or else to `out`. Returns the public key of the identity, which
+/// files are stored for with `--recipient`.
+pub fn handle_keygen_command(
+    output: Option<&str>,
+    out: &mut dyn Write,
+) -> Result<Recipient, Error> {
+    let identity = Identity::generate();
+    let recipient = identity.recipient();
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let contents = format!(
+        "# created: {}\n# public key: {}\n{}\n",
+        created, recipient, identity
+    );
+
+    match output {
+        Some(path) => {
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(path)?.write_all(contents.as_bytes())?;
+        }
+        None => out.write_all(contents.as_bytes())?,
+    }
+
+    Ok(recipient)
+}
+
+/// Reads an identity file written by `handle_keygen_command`. Lines starting
+/// with `#` are comments.
+pub fn read_identity_file(path: &str) -> Result<Identity, Error> {
+    let contents = std::fs::read_to_string(path)?;
+    match contents
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        Some(line) => line.parse(),
+        None => Err(Error::InvalidArgument(format!(
+            "No identity found in {}",
+            path
+        ))),
+    }
+}
+
+/// Derives the search key from a passphrase, with the configured KDF costs
+/// and a fixed salt, so that the same tag has the same token in every pointer.
+fn derive_search_key(passphrase: &str, config: &Config) -> Result<Key, Error> {
+    config
+        .kdf
+        .params()
+        .for_search_key()
+        .derive_key(passphrase.as_bytes())
+}
+
+/// A stored file matched by a search. The file name and size are only known
+/// if the pointer was decrypted.
+#[derive(Debug, PartialEq)]
+pub struct SearchResult {
+    pub cid: String,
+    pub filename: Option<String>,
+    pub size: Option<u64>,
+}
+
+/// Files matched by a search, and the number of files skipped because they
+/// were stored with an earlier format. These cannot be decrypted, so they
+/// must be stored again to be found.
+#[derive(Debug, PartialEq)]
+pub struct SearchResults {
+    pub results: Vec<SearchResult>,
+    pub unsupported: usize,
+}
+
+/// Lists the stored files whose metadata matches `query`. With the empty
+/// query, every file which can be decrypted with `key` is listed.
+///
+/// With a search passphrase, the tags are matched against the tag tokens of
+/// searchable pointers, without decrypting them, and `key` is only used to
+/// read the file name and size of the files matched. Only exact tags can be
+/// searched this way. The tag index is used if there is one, so that other
+/// pointers are not loaded at all.
+pub fn handle_search_command(
+    query: &Query,
+    key: Option<&str>,
+    search_key: Option<&str>,
+    config: &Config,
+) -> Result<SearchResults, Error> {
+    let backend = config.open_backend()?;
+    match search_key {
+        Some(search_key) => {
+            let search_key = derive_search_key(search_key, config)?;
+            search_tokens(query, key, &search_key, backend.as_ref())
+        }
+        None => match key {
+            Some(key) => search(query, key, backend.as_ref()),
+            None => Err(Error::InvalidArgument(
+                "Key (-k) or search key (--search-key) param not provided".to_string(),
+            )),
+        },
+    }
+}
+
+/// Scans every pointer in `backend`, decrypting the metadata of those
+/// encrypted with `key`.
+fn search(query: &Query, key: &str, backend: &dyn Backend) -> Result<SearchResults, Error> {
+    let mut results = vec![];
+    let (pointers, unsupported) = encrypted_pointers(backend)?;
+    for (cid, header) in pointers {
+        let pointer = match decrypt_header(header, key)? {
+            Some(pointer) => pointer,
+            None => continue,
+        };
+        let metadata = match pointer.metadata() {
+            Some(metadata) => metadata,
+            None => continue,
+        };
+
+        if metadata.matches(query)? {
+            results.push(SearchResult {
+                cid,
+                filename: metadata.filename.clone(),
+                size: Some(metadata.size),
+            });
+        }
+    }
+
+    Ok(SearchResults {
+        results,
+        unsupported,
+    })
+}
+
+/// Matches the tokens of the tags in `query` against the tag index or, if
+/// there is none, against the tag tokens of every pointer in `backend` made
+/// searchable under the search key, which are the pointers the index holds.
+fn search_tokens(
+    query: &Query,
+    key: Option<&str>,
+    search_key: &Key,
+    backend: &dyn Backend,
+) -> Result<SearchResults, Error> {
+    let tokens_key = tag_tokens_key(search_key);
+    let query = query.clone().tag_tokens(&tokens_key)?;
 
-    let encrypted_pointer: Pointer = match de::from_slice(&buf) {
-        Ok(p) => p,
-        Err(err) => {
-            println!(
-                "Error deserializing file from contents of {}. Error: {}",
-                file_path, err
-            );
-            std::process::exit(0);
+    // the index only holds pointers of the current format
+    let (cids, unsupported) = match TagIndex::load(backend, search_key)? {
+        Some(index) => {
+            let mut cids = vec![];
+            // skips pointers deleted without updating the index
+            for cid in index.matching(|m| m.matches(&query))? {
+                if backend.has(&cid)? {
+                    cids.push(cid);
+                }
+            }
+            (cids, 0)
+        }
+        None => {
+            let mut cids = vec![];
+            let (pointers, unsupported) = searchable_pointers(backend, &tokens_key)?;
+            for (cid, header) in pointers {
+                if header.matches(&query)? {
+                    cids.push(cid);
+                }
+            }
+            (cids, unsupported)
         }
     };
 
-    // refactor
-    let mut buf_key: [u8; 32] = [0; 32];
-    let mut i = 0;
-    for b in key.as_bytes() {
-        buf_key[i] = *b;
-        i = i + 1;
+    let mut results = vec![];
+    for cid in cids {
+        let metadata = match key {
+            Some(key) => decrypt_header(Pointer::load_header(&cid, backend)?, key)?
+                .and_then(|p| p.metadata().cloned()),
+            None => None,
+        };
+        results.push(SearchResult {
+            cid,
+            filename: metadata.as_ref().and_then(|m| m.filename.clone()),
+            size: metadata.map(|m| m.size),
+        });
     }
 
-    let mut decrypted_pointer = match encrypted_pointer.decrypt(&buf_key) {
-        Ok(p) => p,
-        Err(err) => {
-            println!("Error decrypting file: {:?}", err);
-            std::process::exit(0);
+    Ok(SearchResults {
+        results,
+        unsupported,
+    })
+}
+
+/// Loads the headers of all the encrypted pointers stored with a passphrase.
+/// Pointers stored with an earlier format cannot be decrypted, so they are
+/// counted instead, see `load_pointers`.
+fn encrypted_pointers(backend: &dyn Backend) -> Result<(Vec<(String, Pointer)>, usize), Error> {
+    load_pointers(backend, |header| {
+        header.is_encrypted() && header.kdf_params().is_some()
+    })
+}
+
+/// Loads the headers of all the pointers made searchable under `tokens_key`,
+/// whether they were stored with a passphrase or for recipients.
+fn searchable_pointers(
+    backend: &dyn Backend,
+    tokens_key: &Key,
+) -> Result<(Vec<(String, Pointer)>, usize), Error> {
+    load_pointers(backend, |header| {
+        header.is_encrypted() && header.is_searchable(tokens_key)
+    })
+}
+
+/// Loads the headers of the pointers in `backend` for which `keep` holds,
+/// along with the number of pointers of an earlier format, which are skipped.
+fn load_pointers<F: Fn(&Pointer) -> bool>(
+    backend: &dyn Backend,
+    keep: F,
+) -> Result<(Vec<(String, Pointer)>, usize), Error> {
+    let mut pointers = vec![];
+    let mut unsupported = 0;
+    for cid in backend.list()? {
+        match Pointer::load_header(&cid, backend) {
+            Ok(header) if !header.is_supported() => unsupported += 1,
+            Ok(header) if keep(&header) => pointers.push((cid, header)),
+            Ok(_) => continue,
+            // blocks, other objects, and pointers of earlier formats which
+            // lack fields since made required
+            Err(Error::Decode(_)) => {
+                if Pointer::load_version(&cid, backend)?.is_some() {
+                    unsupported += 1;
+                }
+            }
+            Err(e) => return Err(e),
         }
+    }
+
+    Ok((pointers, unsupported))
+}
+
+/// Decrypts the header of a pointer, or returns `None` if the pointer was
+/// stored with another passphrase.
+fn decrypt_header(header: Pointer, key: &str) -> Result<Option<Pointer>, Error> {
+    let derived_key = match header.derive_key(key) {
+        Ok(derived_key) => derived_key,
+        Err(Error::WrongKey) => return Ok(None),
+        Err(e) => return Err(e),
     };
+    match header.decrypt_header(&derived_key) {
+        Ok(pointer) => Ok(Some(pointer)),
+        Err(Error::WrongKey) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use en_crypt::{cid_of, Chunker, KdfParams, MemoryBackend};
 
-    let mut buffer: Vec<u8> = vec![];
-    let _ = decrypted_pointer.read_to_end(&mut buffer);
+    const SEARCH_KEY: &Key = &Key::new(*b"a search key, not the data key..");
+
+    fn store(backend: &mut MemoryBackend, key: &str, name: &str, tags: &[&str]) -> String {
+        let tags = Tags {
+            values: tags.iter().map(|t| t.to_string()).collect(),
+        };
+        let metadata = Metadata::for_file(Path::new(name), tags).unwrap();
+        let search_key = match key {
+            "passphrase" => SEARCH_KEY,
+            _ => &Key::from(b"another search key.............."),
+        };
+        let mut builder = PointerBuilder::with_passphrase(
+            key,
+            KdfParams::new(64, 1, 1),
+            Chunker::default(),
+            backend,
+        )
+        .unwrap()
+        .metadata(metadata)
+        .searchable(&tag_tokens_key(search_key));
+        builder.write_all(name.as_bytes()).unwrap();
+        builder.finish().unwrap()
+    }
 
-    buffer
+    /// Stores a copy of the pointer `cid` as written before the format was
+    /// versioned, without the fields since made required, and returns its CID.
+    fn store_unsupported(backend: &mut dyn Backend, cid: &str) -> String {
+        let raw = backend.get(cid).unwrap().unwrap();
+        let mut value: serde_cbor::Value = serde_cbor::from_slice(&raw).unwrap();
+        if let serde_cbor::Value::Map(fields) = &mut value {
+            fields.remove(&serde_cbor::Value::Text("version".to_string()));
+            fields.remove(&serde_cbor::Value::Text("cipher".to_string()));
+        }
+        let raw = serde_cbor::to_vec(&value).unwrap();
+        let old = cid_of(&raw).unwrap();
+        backend.put(&old, &raw).unwrap();
+        old
+    }
+
+    fn search_names(query: &str, key: &str, backend: &MemoryBackend) -> Vec<String> {
+        let query = Query::parse(query).unwrap();
+        let mut names: Vec<String> = search(&query, key, backend)
+            .unwrap()
+            .results
+            .into_iter()
+            .map(|r| r.filename.unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn search_tags() {
+        let mut backend = MemoryBackend::new();
+        let invoice = store(
+            &mut backend,
+            "passphrase",
+            "invoice.pdf",
+            &["invoice", "2026"],
+        );
+        store(
+            &mut backend,
+            "passphrase",
+            "receipt.pdf",
+            &["receipt", "2026"],
+        );
+        store(
+            &mut backend,
+            "passphrase",
+            "draft.pdf",
+            &["invoice", "draft"],
+        );
+        store(
+            &mut backend,
+            "another passphrase",
+            "other.pdf",
+            &["invoice"],
+        );
+
+        let results = search(&Query::tag("2026"), "passphrase", &backend)
+            .unwrap()
+            .results;
+        assert_eq!(results.len(), 2);
+        let result = results.iter().find(|r| r.cid == invoice).unwrap();
+        assert_eq!(result.filename, Some("invoice.pdf".to_string()));
+        assert_eq!(result.size, Some("invoice.pdf".len() as u64));
+
+        assert_eq!(
+            search_names("invoice NOT draft", "passphrase", &backend),
+            vec!["invoice.pdf"]
+        );
+        assert_eq!(
+            search_names("invoice OR receipt", "passphrase", &backend),
+            vec!["draft.pdf", "invoice.pdf", "receipt.pdf"]
+        );
+        assert_eq!(
+            search_names("invoice 2026", "passphrase", &backend),
+            vec!["invoice.pdf"]
+        );
+        assert_eq!(
+            search_names(
+                "(inv* OR rec*) NOT filename=draft.*",
+                "passphrase",
+                &backend
+            ),
+            vec!["invoice.pdf", "receipt.pdf"]
+        );
+        assert_eq!(
+            search_names("size>=11 mime=application/pdf", "passphrase", &backend),
+            vec!["invoice.pdf", "receipt.pdf"]
+        );
+        // files stored with another passphrase are not listed
+        assert_eq!(
+            search_names("", "another passphrase", &backend),
+            vec!["other.pdf"]
+        );
+    }
+
+    #[test]
+    fn search_tag_tokens() {
+        let mut backend = MemoryBackend::new();
+        let invoice = store(
+            &mut backend,
+            "passphrase",
+            "invoice.pdf",
+            &["invoice", "2026"],
+        );
+        let receipt = store(
+            &mut backend,
+            "passphrase",
+            "receipt.pdf",
+            &["receipt", "2026"],
+        );
+        store(
+            &mut backend,
+            "another passphrase",
+            "other.pdf",
+            &["invoice", "2026"],
+        );
+
+        let query = |query: &str| Query::parse(query).unwrap();
+
+        // without the data key, only the CIDs are listed
+        let results = search_tokens(&query("2026"), None, SEARCH_KEY, &backend)
+            .unwrap()
+            .results;
+        let mut cids: Vec<String> = results.iter().map(|r| r.cid.clone()).collect();
+        cids.sort();
+        let mut expected = vec![invoice.clone(), receipt];
+        expected.sort();
+        assert_eq!(cids, expected);
+        assert!(results
+            .iter()
+            .all(|r| r.filename.is_none() && r.size.is_none()));
+
+        let results = search_tokens(
+            &query("2026 NOT receipt"),
+            Some("passphrase"),
+            SEARCH_KEY,
+            &backend,
+        )
+        .unwrap()
+        .results;
+        // tag tokens cannot be matched with patterns or metadata fields
+        for query_str in ["inv*", "size>10"] {
+            assert!(matches!(
+                search_tokens(&query(query_str), None, SEARCH_KEY, &backend),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+        assert_eq!(
+            results,
+            vec![SearchResult {
+                cid: invoice,
+                filename: Some("invoice.pdf".to_string()),
+                size: Some("invoice.pdf".len() as u64),
+            }]
+        );
+    }
+
+    #[test]
+    fn search_tag_index() {
+        let mut backend = MemoryBackend::new();
+        let invoice = store(&mut backend, "passphrase", "invoice.pdf", &["invoice"]);
+        let receipt = store(&mut backend, "passphrase", "receipt.pdf", &["receipt"]);
+        let unindexed = store(&mut backend, "passphrase", "other.pdf", &["invoice"]);
+
+        let mut index = TagIndex::default();
+        for cid in [&invoice, &receipt] {
+            let header = Pointer::load_header(cid, &backend).unwrap();
+            index.insert(cid, header.tag_tokens());
+        }
+        index.save(&mut backend, SEARCH_KEY).unwrap();
+
+        // only the indexed pointers are matched once there is an index
+        let query = Query::tag("invoice");
+        let results = search_tokens(&query, Some("passphrase"), SEARCH_KEY, &backend)
+            .unwrap()
+            .results;
+        assert_eq!(
+            results,
+            vec![SearchResult {
+                cid: invoice.clone(),
+                filename: Some("invoice.pdf".to_string()),
+                size: Some("invoice.pdf".len() as u64),
+            }]
+        );
+        assert_ne!(results[0].cid, unindexed);
+
+        // deleted pointers are not listed, even if still in the index
+        let key = Pointer::load_header(&invoice, &backend)
+            .unwrap()
+            .derive_key("passphrase")
+            .unwrap();
+        Pointer::delete(&invoice, &key, &mut backend).unwrap();
+        assert!(search_tokens(&query, None, SEARCH_KEY, &backend)
+            .unwrap()
+            .results
+            .is_empty());
+    }
+
+    #[test]
+    fn search_untagged() {
+        let mut backend = MemoryBackend::new();
+        store(&mut backend, "passphrase", "invoice.pdf", &["invoice"]);
+        let untagged = store(&mut backend, "passphrase", "notes.txt", &[]);
+        // stored under another search key
+        store(&mut backend, "another passphrase", "other.pdf", &[]);
+        let mut builder = PointerBuilder::for_recipients(
+            &[Identity::generate().recipient()],
+            Chunker::default(),
+            &mut backend,
+        )
+        .unwrap()
+        .searchable(&tag_tokens_key(SEARCH_KEY));
+        builder.write_all(b"shared").unwrap();
+        let shared = builder.finish().unwrap();
+        // not searchable
+        let mut builder = PointerBuilder::with_passphrase(
+            "passphrase",
+            KdfParams::new(64, 1, 1),
+            Chunker::default(),
+            &mut backend,
+        )
+        .unwrap();
+        builder.write_all(b"private").unwrap();
+        builder.finish().unwrap();
+
+        let query = Query::parse("NOT invoice").unwrap();
+        let search = |backend: &MemoryBackend| {
+            let mut cids: Vec<String> = search_tokens(&query, None, SEARCH_KEY, backend)
+                .unwrap()
+                .results
+                .into_iter()
+                .map(|r| r.cid)
+                .collect();
+            cids.sort();
+            cids
+        };
+        let mut expected = vec![untagged, shared];
+        expected.sort();
+
+        // the scan and the index match the same pointers
+        assert_eq!(search(&backend), expected);
+        let mut index = TagIndex::default();
+        for cid in backend.list().unwrap() {
+            if let Ok(header) = Pointer::load_header(&cid, &backend) {
+                if header.is_searchable(&tag_tokens_key(SEARCH_KEY)) {
+                    index.insert(&cid, header.tag_tokens());
+                }
+            }
+        }
+        index.save(&mut backend, SEARCH_KEY).unwrap();
+        assert_eq!(search(&backend), expected);
+    }
+
+    #[test]
+    fn search_unsupported() {
+        let mut backend = MemoryBackend::new();
+        let invoice = store(&mut backend, "passphrase", "invoice.pdf", &["invoice"]);
+        store_unsupported(&mut backend, &invoice);
+
+        // pointers of an earlier format are counted, whatever their key
+        let results = search(&Query::tag("invoice"), "another passphrase", &backend).unwrap();
+        assert!(results.results.is_empty());
+        assert_eq!(results.unsupported, 1);
+        let results = search_tokens(&Query::tag("invoice"), None, SEARCH_KEY, &backend).unwrap();
+        assert_eq!(results.results.len(), 1);
+        assert_eq!(results.results[0].cid, invoice);
+        assert_eq!(results.unsupported, 1);
+
+        // the index only holds pointers of the current format
+        let mut index = TagIndex::default();
+        let header = Pointer::load_header(&invoice, &backend).unwrap();
+        index.insert(&invoice, header.tag_tokens());
+        index.save(&mut backend, SEARCH_KEY).unwrap();
+        let results = search_tokens(&Query::tag("invoice"), None, SEARCH_KEY, &backend).unwrap();
+        assert_eq!(results.results.len(), 1);
+        assert_eq!(results.unsupported, 0);
+    }
+
+    #[test]
+    fn store_for_recipients() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            backends: vec![crate::config::BackendConfig::Fs {
+                path: dir.path().join("store"),
+            }],
+            ..Config::default()
+        };
+
+        let identity_path = dir.path().join("identity.txt");
+        let identity_path = identity_path.to_str().unwrap();
+        let mut out = vec![];
+        let recipient = handle_keygen_command(Some(identity_path), &mut out).unwrap();
+        assert!(out.is_empty());
+        // an existing identity is not overwritten
+        assert!(handle_keygen_command(Some(identity_path), &mut out).is_err());
+        let identity = read_identity_file(identity_path).unwrap();
+        assert_eq!(identity.recipient(), recipient);
+
+        let file_path = dir.path().join("report.txt");
+        std::fs::write(&file_path, b"for alice and bob").unwrap();
+        let other = Identity::generate();
+        let cid = handle_store_command(
+            file_path.to_str().unwrap(),
+            None,
+            &[recipient, other.recipient()],
+            None,
+            vec![],
+            &config,
+        )
+        .unwrap();
+
+        for secret in [Secret::Identity(identity), Secret::Identity(other)] {
+            let mut out = vec![];
+            handle_read_command(&cid, &secret, &config, &mut out).unwrap();
+            assert_eq!(out, b"for alice and bob");
+        }
+        let stranger = Secret::Identity(Identity::generate());
+        assert!(matches!(
+            handle_read_command(&cid, &stranger, &config, &mut vec![]),
+            Err(Error::WrongKey)
+        ));
+
+        // a passphrase is not silently ignored in favour of the recipients
+        assert!(matches!(
+            handle_store_command(
+                file_path.to_str().unwrap(),
+                Some("passphrase"),
+                &[Identity::generate().recipient()],
+                None,
+                vec![],
+                &config,
+            ),
+            Err(Error::InvalidArgument(_))
+        ));
+        // before the input is opened
+        let missing = dir.path().join("missing.txt");
+        for (key, recipients) in [
+            (Some("passphrase"), vec![Identity::generate().recipient()]),
+            (None, vec![]),
+        ] {
+            assert!(matches!(
+                handle_store_command(
+                    missing.to_str().unwrap(),
+                    key,
+                    &recipients,
+                    None,
+                    vec![],
+                    &config,
+                ),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn migrate() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            backends: vec![crate::config::BackendConfig::Fs {
+                path: dir.path().join("store"),
+            }],
+            kdf: crate::config::KdfConfig {
+                m_cost: 64,
+                t_cost: 1,
+                p_cost: 1,
+            },
+            ..Config::default()
+        };
+
+        // a pointer written by the first version of en-crypt, as raw CBOR and
+        // as that version printed it
+        let legacy = include_bytes!("../tests/fixtures/legacy_pointer.cbor");
+        let raw_path = dir.path().join("legacy.cbor");
+        std::fs::write(&raw_path, legacy).unwrap();
+        let printed_path = dir.path().join("legacy.txt");
+        std::fs::write(&printed_path, format!("{:?}\n", legacy.to_vec())).unwrap();
+
+        let data: Vec<u8> = (0..4103).map(|i| (i % 251) as u8).collect();
+        for path in [&raw_path, &printed_path] {
+            let path = path.to_str().unwrap();
+            let cid = handle_migrate_command(path, "legacy passphrase", &config).unwrap();
+            let secret = Secret::Passphrase("legacy passphrase".to_string());
+            let mut out = vec![];
+            handle_read_command(&cid, &secret, &config, &mut out).unwrap();
+            assert_eq!(out, data);
+
+            assert!(matches!(
+                handle_migrate_command(path, "wrong passphrase", &config),
+                Err(Error::WrongKey)
+            ));
+        }
+    }
+
+    #[test]
+    fn rekey() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            backends: vec![crate::config::BackendConfig::Fs {
+                path: dir.path().join("store"),
+            }],
+            kdf: crate::config::KdfConfig {
+                m_cost: 64,
+                t_cost: 1,
+                p_cost: 1,
+            },
+            ..Config::default()
+        };
+        let store_file = |name: &str, key: &str, tags: &[&str]| {
+            let file_path = dir.path().join(name);
+            std::fs::write(&file_path, name).unwrap();
+            handle_store_command(
+                file_path.to_str().unwrap(),
+                Some(key),
+                &[],
+                Some("search"),
+                tags.iter().map(|t| t.to_string()).collect(),
+                &config,
+            )
+            .unwrap()
+        };
+        let invoice = store_file("invoice.pdf", "old", &["invoice"]);
+        let other = store_file("other.pdf", "another", &["invoice"]);
+
+        let mut backend = config.open_backend().unwrap();
+        let old = store_unsupported(backend.as_mut(), &invoice);
+        let links = Pointer::load_header(&invoice, backend.as_ref())
+            .unwrap()
+            .links()
+            .to_vec();
+        let objects = backend.list().unwrap().len();
+        drop(backend);
+
+        let rekeyed = handle_rekey_command("old", "new", Some("search"), &config).unwrap();
+        // the pointer of an earlier format is skipped and kept
+        assert_eq!(rekeyed.unsupported, 1);
+        assert_eq!(rekeyed.cids.len(), 1);
+        let (cid, new_cid) = &rekeyed.cids[0];
+        assert_eq!(cid, &invoice);
+
+        // the old header is replaced, and the blocks are kept
+        let backend = config.open_backend().unwrap();
+        assert!(!backend.has(&invoice).unwrap());
+        assert!(backend.has(&old).unwrap());
+        assert_eq!(backend.list().unwrap().len(), objects);
+        let header = Pointer::load_header(new_cid, backend.as_ref()).unwrap();
+        assert_eq!(header.links(), links.as_slice());
+        drop(backend);
+
+        let mut out = vec![];
+        let secret = Secret::Passphrase("new".to_string());
+        handle_read_command(new_cid, &secret, &config, &mut out).unwrap();
+        assert_eq!(out, b"invoice.pdf");
+        let secret = Secret::Passphrase("old".to_string());
+        assert!(matches!(
+            handle_read_command(new_cid, &secret, &config, &mut vec![]),
+            Err(Error::WrongKey)
+        ));
+
+        // the file stored with another passphrase is not changed
+        let mut out = vec![];
+        let secret = Secret::Passphrase("another".to_string());
+        handle_read_command(&other, &secret, &config, &mut out).unwrap();
+        assert_eq!(out, b"other.pdf");
+
+        // the tag index refers to the new CID
+        let mut found: Vec<String> =
+            handle_search_command(&Query::tag("invoice"), None, Some("search"), &config)
+                .unwrap()
+                .results
+                .into_iter()
+                .map(|r| r.cid)
+                .collect();
+        found.sort();
+        let mut expected = vec![new_cid.clone(), other];
+        expected.sort();
+        assert_eq!(found, expected);
+    }
 }
@@ -0,0 +1,481 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use en_crypt::structs::DEFAULT_BLOCK_SIZE;
+use en_crypt::{Backend, Chunker, Cipher, Error, FsBackend, KdfParams, MirrorBackend};
+
+/// Environment variables which override the values in the config file.
+pub const ENV_BACKEND: &str = "EN_CRYPT_BACKEND";
+pub const ENV_KEY_FILE: &str = "EN_CRYPT_KEY_FILE";
+pub const ENV_CHUNK_SIZE: &str = "EN_CRYPT_CHUNK_SIZE";
+pub const ENV_CIPHER: &str = "EN_CRYPT_CIPHER";
+pub const ENV_KDF_M_COST: &str = "EN_CRYPT_KDF_M_COST";
+pub const ENV_KDF_T_COST: &str = "EN_CRYPT_KDF_T_COST";
+pub const ENV_KDF_P_COST: &str = "EN_CRYPT_KDF_P_COST";
+pub const ENV_OUTPUT: &str = "EN_CRYPT_OUTPUT";
+
+/// Config holds the CLI settings, loaded from a JSON file. Every field is
+/// optional in the file and falls back to its default.
+///
+/// ```json
+/// {
+///   "backends": [{ "type": "fs", "path": "./store" }],
+///   "key": { "env": "PROJECT_PASSPHRASE" },
+///   "search_key": { "file": "search-key.txt" },
+///   "chunk_size": 262144,
+///   "chunker": { "type": "fastcdc", "min": 65536, "avg": 262144, "max": 1048576 },
+///   "convergent": false,
+///   "cipher": "aes-256-gcm",
+///   "kdf": { "m_cost": 19456, "t_cost": 2, "p_cost": 1 },
+///   "output": "json"
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub backends: Vec<BackendConfig>,
+    pub key: Option<KeySource>,
+    /// Where to read the search passphrase from. Stored files are made
+    /// searchable by their tags when it is set
+    pub search_key: Option<KeySource>,
+    pub chunk_size: usize,
+    /// How files are split in blocks. Defaults to fixed size blocks of
+    /// `chunk_size` bytes
+    pub chunker: Option<Chunker>,
+    /// Encrypts blocks in convergent mode, so that identical blocks are
+    /// stored once. Reveals whether a stored file contains a known block
+    pub convergent: bool,
+    /// Cipher new files are encrypted with. Files are read with the cipher
+    /// recorded in their pointer
+    pub cipher: Cipher,
+    pub kdf: KdfConfig,
+    pub output: OutputFormat,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum BackendConfig {
+    /// Local directory backend
+    Fs { path: PathBuf },
+}
+
+/// Where to read the passphrase from when it is not passed in the command line.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum KeySource {
+    /// Name of the environment variable holding the passphrase
+    Env(String),
+    /// Path to a file holding the passphrase
+    File(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KdfConfig {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            backends: vec![BackendConfig::Fs {
+                path: en_crypt_dir().join("store"),
+            }],
+            key: None,
+            search_key: None,
+            chunk_size: DEFAULT_BLOCK_SIZE,
+            chunker: None,
+            convergent: false,
+            cipher: Cipher::Aes256Gcm,
+            kdf: KdfConfig::default(),
+            output: OutputFormat::Text,
+        }
+    }
+}
+
+impl Default for KdfConfig {
+    fn default() -> Self {
+        let params = KdfParams::default();
+        KdfConfig {
+            m_cost: params.m_cost,
+            t_cost: params.t_cost,
+            p_cost: params.p_cost,
+        }
+    }
+}
+
+impl KdfConfig {
+    /// Returns a new set of KDF parameters, with a fresh salt.
+    pub fn params(&self) -> KdfParams {
+        KdfParams::new(self.m_cost, self.t_cost, self.p_cost)
+    }
+}
+
+/// Returns the en-crypt home directory, ~/.en-crypt
+pub fn en_crypt_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home).join(".en-crypt")
+}
+
+/// Returns the default config file path, ~/.en-crypt/config.json
+pub fn default_config_path() -> PathBuf {
+    en_crypt_dir().join("config.json")
+}
+
+impl Config {
+    /// Loads the configuration used by the CLI. If `path` is not set, the
+    /// default config file is used if it exists. Environment variable overrides
+    /// are applied on top of the file and the result is validated.
+    pub fn load(path: Option<&str>) -> Result<Config, Error> {
+        let mut config = match path {
+            Some(p) => Config::from_file(Path::new(p))?,
+            None => {
+                let default_path = default_config_path();
+                if default_path.is_file() {
+                    Config::from_file(&default_path)?
+                } else {
+                    Config::default()
+                }
+            }
+        };
+
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Parses a config file. Relative paths in the file are resolved against
+    /// the directory of the file, so that a config can be checked in together
+    /// with a project.
+    pub fn from_file(path: &Path) -> Result<Config, Error> {
+        let raw = std::fs::read(path)
+            .map_err(|e| Error::Config(format!("Error reading {}: {}", path.display(), e)))?;
+        let mut config: Config = serde_json::from_slice(&raw)
+            .map_err(|e| Error::Config(format!("Error parsing {}: {}", path.display(), e)))?;
+
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        config.resolve_paths(base);
+
+        Ok(config)
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        for backend in self.backends.iter_mut() {
+            match backend {
+                BackendConfig::Fs { path } => *path = resolve_path(base, path),
+            }
+        }
+        for key in [&mut self.key, &mut self.search_key] {
+            if let Some(KeySource::File(path)) = key {
+                *path = resolve_path(base, path);
+            }
+        }
+    }
+
+    /// Overrides config values with environment variables. `get` returns the
+    /// value of an environment variable, if set.
+    pub fn apply_env<F: Fn(&str) -> Option<String>>(&mut self, get: F) -> Result<(), Error> {
+        if let Some(path) = get(ENV_BACKEND) {
+            self.backends = vec![BackendConfig::Fs {
+                path: resolve_path(Path::new(""), Path::new(&path)),
+            }];
+        }
+        if let Some(path) = get(ENV_KEY_FILE) {
+            self.key = Some(KeySource::File(PathBuf::from(path)));
+        }
+        if let Some(size) = get(ENV_CHUNK_SIZE) {
+            // a fixed block size overrides content-defined chunking
+            self.chunk_size = parse_env(ENV_CHUNK_SIZE, &size)?;
+            self.chunker = None;
+        }
+        if let Some(cipher) = get(ENV_CIPHER) {
+            self.cipher = parse_env_json(ENV_CIPHER, &cipher)?;
+        }
+        if let Some(cost) = get(ENV_KDF_M_COST) {
+            self.kdf.m_cost = parse_env(ENV_KDF_M_COST, &cost)?;
+        }
+        if let Some(cost) = get(ENV_KDF_T_COST) {
+            self.kdf.t_cost = parse_env(ENV_KDF_T_COST, &cost)?;
+        }
+        if let Some(cost) = get(ENV_KDF_P_COST) {
+            self.kdf.p_cost = parse_env(ENV_KDF_P_COST, &cost)?;
+        }
+        if let Some(output) = get(ENV_OUTPUT) {
+            self.output = parse_env_json(ENV_OUTPUT, &output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the configuration is usable.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.backends.is_empty() {
+            return Err(Error::Config(
+                "at least one backend is required".to_string(),
+            ));
+        }
+        for backend in &self.backends {
+            match backend {
+                BackendConfig::Fs { path } => {
+                    if path.as_os_str().is_empty() {
+                        return Err(Error::Config("fs backend path is empty".to_string()));
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = self.chunker().validate() {
+            return Err(Error::Config(format!("chunker: {}", e)));
+        }
+
+        if let Err(e) = self.kdf.params().validate() {
+            return Err(Error::Config(format!("kdf parameters: {}", e)));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the chunker used to split files in blocks.
+    pub fn chunker(&self) -> Chunker {
+        match self.chunker {
+            Some(chunker) => chunker,
+            None => Chunker::Fixed {
+                size: self.chunk_size,
+            },
+        }
+    }
+
+    /// Opens the configured backends. Objects are mirrored across all of them
+    /// when more than one is configured.
+    pub fn open_backend(&self) -> Result<Box<dyn Backend>, Error> {
+        let mut backends: Vec<Box<dyn Backend>> = vec![];
+        for backend in &self.backends {
+            match backend {
+                BackendConfig::Fs { path } => backends.push(Box::new(FsBackend::new(path)?)),
+            }
+        }
+
+        if backends.len() == 1 {
+            return Ok(backends.remove(0));
+        }
+        Ok(Box::new(MirrorBackend::new(backends)))
+    }
+
+    /// Reads the passphrase from the configured key source.
+    pub fn read_key(&self) -> Result<Option<String>, Error> {
+        self.key.as_ref().map(KeySource::read).transpose()
+    }
+
+    /// Reads the search passphrase from the configured search key source.
+    pub fn read_search_key(&self) -> Result<Option<String>, Error> {
+        self.search_key.as_ref().map(KeySource::read).transpose()
+    }
+}
+
+impl KeySource {
+    fn read(&self) -> Result<String, Error> {
+        match self {
+            KeySource::Env(name) => std::env::var(name).map_err(|_| {
+                Error::Config(format!("key environment variable {} is not set", name))
+            }),
+            KeySource::File(path) => match std::fs::read_to_string(path) {
+                Ok(key) => Ok(key.trim_end_matches(&['\r', '\n'][..]).to_string()),
+                Err(e) => Err(Error::Config(format!(
+                    "error reading key file {}: {}",
+                    path.display(),
+                    e
+                ))),
+            },
+        }
+    }
+}
+
+fn resolve_path(base: &Path, path: &Path) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix("~") {
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        return PathBuf::from(home).join(rest);
+    }
+    base.join(path)
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::Config(format!("invalid value for {}: {:?}", name, value)))
+}
+
+fn parse_env_json<T: serde::de::DeserializeOwned>(name: &str, value: &str) -> Result<T, Error> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|e| Error::Config(format!("invalid value for {}: {}", name, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parse_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "backends": [{ "type": "fs", "path": "store" }, { "type": "fs", "path": "/mnt/backup" }],
+                "key": { "file": "secret.txt" },
+                "search_key": { "file": "search.txt" },
+                "chunk_size": 1024,
+                "chunker": { "type": "fastcdc", "min": 128, "avg": 512, "max": 2048 },
+                "convergent": true,
+                "cipher": "aes-256-gcm",
+                "kdf": { "m_cost": 64, "t_cost": 1, "p_cost": 1 },
+                "output": "json"
+            }"#,
+        )
+        .unwrap();
+
+        let config = Config::from_file(&path).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.backends,
+            vec![
+                BackendConfig::Fs {
+                    path: dir.path().join("store")
+                },
+                BackendConfig::Fs {
+                    path: PathBuf::from("/mnt/backup")
+                },
+            ]
+        );
+        assert_eq!(
+            config.key,
+            Some(KeySource::File(dir.path().join("secret.txt")))
+        );
+        assert_eq!(config.chunk_size, 1024);
+        assert_eq!(
+            config.chunker(),
+            Chunker::FastCdc {
+                min: 128,
+                avg: 512,
+                max: 2048
+            }
+        );
+        assert!(config.convergent);
+        assert_eq!(config.kdf.m_cost, 64);
+        assert_eq!(config.output, OutputFormat::Json);
+
+        std::fs::write(dir.path().join("secret.txt"), "passphrase\n").unwrap();
+        assert_eq!(config.read_key().unwrap(), Some("passphrase".to_string()));
+        std::fs::write(dir.path().join("search.txt"), "search passphrase\n").unwrap();
+        assert_eq!(
+            config.read_search_key().unwrap(),
+            Some("search passphrase".to_string())
+        );
+    }
+
+    #[test]
+    fn defaults() {
+        let config: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, Config::default());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_config() {
+        // unknown fields and values are rejected
+        assert!(serde_json::from_str::<Config>(r#"{ "chunk": 10 }"#).is_err());
+        assert!(serde_json::from_str::<Config>(r#"{ "cipher": "rot13" }"#).is_err());
+        assert!(serde_json::from_str::<Config>(r#"{ "output": "xml" }"#).is_err());
+        assert!(serde_json::from_str::<Config>(r#"{ "backends": [{ "type": "ftp" }] }"#).is_err());
+
+        let config = Config {
+            chunk_size: en_crypt::structs::MAX_BLOCK_SIZE + 1,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            chunker: Some(Chunker::FastCdc {
+                min: 1,
+                avg: 2,
+                max: 3,
+            }),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            backends: vec![],
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.kdf.p_cost = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn env_overrides() {
+        let env: HashMap<&str, &str> = [
+            (ENV_BACKEND, "/tmp/en-crypt"),
+            (ENV_CHUNK_SIZE, "512"),
+            (ENV_CIPHER, "chacha20-poly1305"),
+            (ENV_KDF_T_COST, "3"),
+            (ENV_OUTPUT, "json"),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        let mut config = Config::default();
+        config
+            .apply_env(|name| env.get(name).map(|v| v.to_string()))
+            .unwrap();
+
+        assert_eq!(
+            config.backends,
+            vec![BackendConfig::Fs {
+                path: PathBuf::from("/tmp/en-crypt")
+            }]
+        );
+        assert_eq!(config.chunk_size, 512);
+        assert_eq!(config.cipher, Cipher::ChaCha20Poly1305);
+        assert_eq!(config.kdf.t_cost, 3);
+        assert_eq!(config.output, OutputFormat::Json);
+
+        // the block size also overrides a configured chunker
+        let mut config = Config {
+            chunker: Some(Chunker::FastCdc {
+                min: 128,
+                avg: 512,
+                max: 2048,
+            }),
+            ..Config::default()
+        };
+        config
+            .apply_env(|name| env.get(name).map(|v| v.to_string()))
+            .unwrap();
+        assert_eq!(config.chunker(), Chunker::Fixed { size: 512 });
+
+        let mut config = Config::default();
+        let res = config.apply_env(|name| {
+            if name == ENV_CHUNK_SIZE {
+                Some("big".to_string())
+            } else {
+                None
+            }
+        });
+        assert!(res.is_err());
+    }
+}
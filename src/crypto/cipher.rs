@@ -0,0 +1,197 @@
+use aes_gcm::aead::{generic_array::GenericArray, Aead, KeyInit, Payload};
+use aes_gcm::Aes256Gcm;
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use super::{random_bytes, Key};
+use crate::error::Error;
+
+/// Cipher is the AEAD cipher suite the blocks and manifest of a pointer are
+/// encrypted with. It is recorded in the pointer header, so pointers encrypted
+/// with different ciphers can be stored and read side by side.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cipher {
+    /// AES-256-GCM, the fastest with hardware AES support
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    /// ChaCha20-Poly1305, fast in software on machines without AES support
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+    /// XChaCha20-Poly1305, whose 192 bit nonces can be picked at random
+    /// without any practical risk of collision
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+    /// AES-256-GCM-SIV, which is nonce misuse resistant: a repeated nonce only
+    /// reveals whether the same message was encrypted twice
+    #[serde(rename = "aes-256-gcm-siv")]
+    Aes256GcmSiv,
+}
+
+impl Cipher {
+    /// Every supported cipher
+    pub const ALL: [Cipher; 4] = [
+        Cipher::Aes256Gcm,
+        Cipher::ChaCha20Poly1305,
+        Cipher::XChaCha20Poly1305,
+        Cipher::Aes256GcmSiv,
+    ];
+
+    /// Name of the cipher, as set in the config and with `--cipher`
+    pub fn name(self) -> &'static str {
+        match self {
+            Cipher::Aes256Gcm => "aes-256-gcm",
+            Cipher::ChaCha20Poly1305 => "chacha20-poly1305",
+            Cipher::XChaCha20Poly1305 => "xchacha20-poly1305",
+            Cipher::Aes256GcmSiv => "aes-256-gcm-siv",
+        }
+    }
+
+    /// Size of the nonces of the cipher, in bytes
+    pub fn nonce_size(self) -> usize {
+        match self {
+            Cipher::XChaCha20Poly1305 => 24,
+            _ => 12,
+        }
+    }
+
+    /// Generates a random nonce for the cipher.
+    pub fn random_nonce(self) -> Vec<u8> {
+        random_bytes(self.nonce_size())
+    }
+
+    /// Nonce of keys which only ever encrypt a single plaintext, such as the
+    /// keys of convergent blocks, so that a fixed nonce is never reused with
+    /// different data.
+    pub fn zero_nonce(self) -> Vec<u8> {
+        vec![0; self.nonce_size()]
+    }
+
+    /// Encrypts `msg`, authenticating `ad` together with it. Returns `None` if
+    /// the nonce does not have the size of the cipher nonces.
+    pub fn seal(self, key: &Key, nonce: &[u8], msg: &[u8], ad: &[u8]) -> Option<Vec<u8>> {
+        if nonce.len() != self.nonce_size() {
+            return None;
+        }
+        let payload = Payload { msg, aad: ad };
+        match self {
+            Cipher::Aes256Gcm => seal::<Aes256Gcm>(key, nonce, payload),
+            Cipher::ChaCha20Poly1305 => seal::<ChaCha20Poly1305>(key, nonce, payload),
+            Cipher::XChaCha20Poly1305 => seal::<XChaCha20Poly1305>(key, nonce, payload),
+            Cipher::Aes256GcmSiv => seal::<Aes256GcmSiv>(key, nonce, payload),
+        }
+    }
+
+    /// Decrypts a ciphertext sealed with `seal`. Returns `None` if it fails
+    /// authentication, either because it was tampered with or because it was
+    /// encrypted with another key, nonce or associated data.
+    pub fn open(self, key: &Key, nonce: &[u8], ctext: &[u8], ad: &[u8]) -> Option<Vec<u8>> {
+        if nonce.len() != self.nonce_size() {
+            return None;
+        }
+        let payload = Payload {
+            msg: ctext,
+            aad: ad,
+        };
+        match self {
+            Cipher::Aes256Gcm => open::<Aes256Gcm>(key, nonce, payload),
+            Cipher::ChaCha20Poly1305 => open::<ChaCha20Poly1305>(key, nonce, payload),
+            Cipher::XChaCha20Poly1305 => open::<XChaCha20Poly1305>(key, nonce, payload),
+            Cipher::Aes256GcmSiv => open::<Aes256GcmSiv>(key, nonce, payload),
+        }
+    }
+}
+
+fn seal<A: Aead + KeyInit>(key: &Key, nonce: &[u8], payload: Payload) -> Option<Vec<u8>> {
+    A::new(GenericArray::from_slice(key.as_bytes()))
+        .encrypt(GenericArray::from_slice(nonce), payload)
+        .ok()
+}
+
+fn open<A: Aead + KeyInit>(key: &Key, nonce: &[u8], payload: Payload) -> Option<Vec<u8>> {
+    A::new(GenericArray::from_slice(key.as_bytes()))
+        .decrypt(GenericArray::from_slice(nonce), payload)
+        .ok()
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Cipher::ALL
+            .iter()
+            .copied()
+            .find(|c| c.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Cipher::ALL.iter().map(|c| c.name()).collect();
+                Error::InvalidArgument(format!(
+                    "Unsupported cipher {}, expected one of {}",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &Key = &Key::new(*b"hello darkness my good ol friend");
+
+    #[test]
+    fn seal_open() {
+        for cipher in Cipher::ALL {
+            let nonce = cipher.random_nonce();
+            assert_eq!(nonce.len(), cipher.nonce_size());
+            assert_ne!(nonce, cipher.random_nonce());
+
+            let ctext = cipher.seal(KEY, &nonce, b"data", b"ad").unwrap();
+            assert_eq!(ctext.len(), 4 + 16);
+            assert_eq!(cipher.open(KEY, &nonce, &ctext, b"ad").unwrap(), b"data");
+
+            assert!(cipher.open(KEY, &nonce, &ctext, b"other ad").is_none());
+            assert!(cipher
+                .open(&Key::from([0; 32]), &nonce, &ctext, b"ad")
+                .is_none());
+            let mut tampered = ctext.clone();
+            tampered[0] ^= 1;
+            assert!(cipher.open(KEY, &nonce, &tampered, b"ad").is_none());
+
+            // ciphertexts do not open with another cipher
+            for other in Cipher::ALL.iter().filter(|c| **c != cipher) {
+                let nonce = &nonce[..other.nonce_size().min(nonce.len())];
+                assert!(other.open(KEY, nonce, &ctext, b"ad").is_none());
+            }
+
+            assert!(cipher.seal(KEY, &[0; 8], b"data", b"ad").is_none());
+            assert!(cipher.open(KEY, &[0; 8], &ctext, b"ad").is_none());
+        }
+        assert_eq!(Cipher::XChaCha20Poly1305.nonce_size(), 24);
+    }
+
+    #[test]
+    fn names() {
+        for cipher in Cipher::ALL {
+            assert_eq!(cipher.name().parse::<Cipher>().unwrap(), cipher);
+            assert_eq!(
+                serde_json::to_string(&cipher).unwrap(),
+                format!("\"{}\"", cipher)
+            );
+        }
+        assert_eq!(Cipher::default(), Cipher::Aes256Gcm);
+        assert!(matches!(
+            "rot13".parse::<Cipher>(),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
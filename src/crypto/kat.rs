@@ -0,0 +1,154 @@
+//! Known-answer tests of the cryptographic primitives, so that a dependency
+//! update or a refactoring cannot silently change what is stored. Expected
+//! values were computed with an independent implementation (Python
+//! `cryptography`) and, where there is one, checked against the RFC vector.
+
+use super::*;
+
+const KEY: Key = Key::new([
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f,
+]);
+const MSG: &[u8] = b"en-crypt known answer";
+const AD: &[u8] = b"associated data";
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// Nonce of `len` bytes counting up from 0
+fn nonce(len: usize) -> Vec<u8> {
+    (0..len as u8).collect()
+}
+
+#[test]
+fn aead() {
+    let vectors = [
+        (
+            Cipher::Aes256Gcm,
+            "226cfb78b79cb26fad2af9e4c687580ceda5f05182f86df7992ac47a3e6b76495353ba857b",
+        ),
+        (
+            Cipher::ChaCha20Poly1305,
+            "ec9525635b6ed53497e8519cef732e02a703c58223b2b2e2b0b9b23de2816e2cc948097c6f",
+        ),
+        (
+            Cipher::XChaCha20Poly1305,
+            "fbac221ce2abfdda132f48a1bc3c888925345fc08ff0b73dc83731d15bef79c51074ccccb6",
+        ),
+        (
+            Cipher::Aes256GcmSiv,
+            "d12067281643aa4dfc77e37e3e9a6a6199020cc89f9d1813bb1cfea0dd97b6e9ac4bac7b79",
+        ),
+    ];
+
+    for (cipher, expected) in vectors.iter() {
+        let nonce = nonce(cipher.nonce_size());
+        let ctext = cipher.seal(&KEY, &nonce, MSG, AD).unwrap();
+        assert_eq!(to_hex(&ctext), *expected, "{}", cipher);
+        assert_eq!(
+            cipher.open(&KEY, &nonce, &from_hex(expected), AD).unwrap(),
+            MSG
+        );
+    }
+}
+
+#[test]
+fn hkdf_sha256() {
+    // RFC 5869, test case 1, truncated to the 32 bytes of a key
+    let okm = hkdf(
+        &[0x0b; 22],
+        &from_hex("000102030405060708090a0b0c"),
+        &from_hex("f0f1f2f3f4f5f6f7f8f9"),
+    );
+    assert_eq!(
+        to_hex(okm.as_bytes()),
+        "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf"
+    );
+
+    assert_eq!(
+        to_hex(hkdf(KEY.as_bytes(), b"salt", b"info").as_bytes()),
+        "64cc2a23b7a1da4310420350405fdf5d632cb9bda3700d753e96cbcfeb81d449"
+    );
+    assert_eq!(
+        to_hex(KEY.derive_subkey(b"blocks").as_bytes()),
+        "0735ce4cc9e3a9dffb60298bcfb44c25ba7751f30dcde12a9954a0186571279f"
+    );
+
+    // key hierarchy
+    assert_eq!(
+        to_hex(blocks_key(&KEY).as_bytes()),
+        "32cf662f6687f05b1e3235b019535d66d0d4b198f4aeeb937b3120a70f748649"
+    );
+    assert_eq!(
+        to_hex(metadata_key(&KEY).as_bytes()),
+        "3977d80c45675eecf064791d1e6b1e4b4d4a4a1af1e1c4dcac57a66efffd4571"
+    );
+    assert_eq!(
+        to_hex(tag_tokens_key(&KEY).as_bytes()),
+        "52c52428eee9ea49530141db74a6cc0d9a9c13a90eda5f6b9c44ad32460e9a08"
+    );
+    assert_eq!(
+        to_hex(index_key(&KEY).as_bytes()),
+        "86922a59951a958f2a593e0b0077ab46cd2813cc31f666171545b2e75ba049f8"
+    );
+}
+
+#[test]
+fn hmac_sha256() {
+    assert_eq!(
+        tag_token(&KEY, "invoice"),
+        "bac924ee3ab4879a38f37ee48077cc3f1305cba879524db5b56fe2d99836c1f7"
+    );
+    assert_eq!(
+        tag_token(&tag_tokens_key(&KEY), "invoice"),
+        "4ed981e62e1afb65f3bc9e3642e7aafae2f80b6d2b57ac81383deb81e3712019"
+    );
+    assert_eq!(
+        member_token(&tag_tokens_key(&KEY)),
+        "f9312128aceb59db66a3fbf11439ed2a1fb5237001cc7b10e88840964eff5e86"
+    );
+    assert_eq!(index_ref(&KEY), "tagindexad1e4162ab5c4573aeb5cfcb0761b51c");
+    assert_eq!(
+        to_hex(&key_commitment(&KEY, &nonce(16))),
+        "f96b22566663db781a168e03764f1dc8adf00e647ef6866e7276edb6907edcad"
+    );
+}
+
+#[test]
+fn sha256() {
+    assert_eq!(
+        to_hex(convergent_key(b"block").as_bytes()),
+        "83e4cb698950f6b5c52c2aa25513d3cddc1ef4974f5cb9e94a70dd9e92aed30b"
+    );
+}
+
+#[test]
+fn argon2id() {
+    let params = KdfParams {
+        salt: nonce(16),
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+    assert_eq!(
+        to_hex(params.derive_key(b"passphrase").unwrap().as_bytes()),
+        "0e40e8291684807a00ec6b92fffd55ce37a00b91bfa5b41a18ed7802b707cda8"
+    );
+}
+
+#[test]
+fn x25519() {
+    // RFC 7748, section 6.1
+    let identity: Identity =
+        "ensec77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a"
+            .parse()
+            .unwrap();
+    assert_eq!(
+        identity.recipient().to_string(),
+        "enpub8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+    );
+}
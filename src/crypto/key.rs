@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::hkdf;
+
+pub const KEY_SIZE_BYTES: usize = 32;
+
+/// Key is a 256 bit symmetric key. It is zeroed when dropped and is not
+/// printed by `Debug`, so that it does not linger in memory nor leak in logs.
+/// Keys are compared in constant time.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Key([u8; KEY_SIZE_BYTES]);
+
+impl Key {
+    pub const fn new(bytes: [u8; KEY_SIZE_BYTES]) -> Key {
+        Key(bytes)
+    }
+
+    /// Reads a key from a slice, or returns `None` if it is not 32 bytes long.
+    pub fn from_slice(bytes: &[u8]) -> Option<Key> {
+        bytes.try_into().ok().map(Key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; KEY_SIZE_BYTES] {
+        &self.0
+    }
+
+    pub(super) fn as_mut_bytes(&mut self) -> &mut [u8; KEY_SIZE_BYTES] {
+        &mut self.0
+    }
+
+    /// Derives an independent key for the purpose named by `info` with
+    /// HKDF-SHA256, so that a single key is never used for two purposes.
+    pub fn derive_subkey(&self, info: &[u8]) -> Key {
+        hkdf(&self.0, &[], info)
+    }
+}
+
+impl From<[u8; KEY_SIZE_BYTES]> for Key {
+    fn from(bytes: [u8; KEY_SIZE_BYTES]) -> Key {
+        Key(bytes)
+    }
+}
+
+impl From<&[u8; KEY_SIZE_BYTES]> for Key {
+    fn from(bytes: &[u8; KEY_SIZE_BYTES]) -> Key {
+        Key(*bytes)
+    }
+}
+
+impl AsRef<[u8]> for Key {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        let diff = self.0.iter().zip(&other.0).fold(0, |d, (a, b)| d | (a ^ b));
+        diff == 0
+    }
+}
+
+impl Eq for Key {}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for Key {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key() {
+        let key = Key::from(b"hello darkness my good ol friend");
+        assert!(!format!("{:?}", key).contains("hello"));
+        assert_eq!(key, Key::new(*b"hello darkness my good ol friend"));
+        assert_ne!(key, Key::from([0; KEY_SIZE_BYTES]));
+
+        assert_eq!(Key::from_slice(key.as_ref()), Some(key.clone()));
+        assert!(Key::from_slice(&[0; 16]).is_none());
+
+        // serialised as the bytes of the key, as before keys were a type
+        let raw = serde_cbor::to_vec(&key).unwrap();
+        assert_eq!(raw, serde_cbor::to_vec(key.as_bytes()).unwrap());
+        assert_eq!(serde_cbor::from_slice::<Key>(&raw).unwrap(), key);
+
+        let subkey = key.derive_subkey(b"blocks");
+        assert_ne!(subkey, key);
+        assert_eq!(subkey, key.derive_subkey(b"blocks"));
+        assert_ne!(subkey, key.derive_subkey(b"metadata"));
+    }
+}
@@ -1 +1,507 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
 
+use crate::error::Error;
+
+mod cipher;
+#[cfg(test)]
+mod kat;
+mod key;
+mod recipient;
+
+pub use cipher::Cipher;
+pub use key::{Key, KEY_SIZE_BYTES};
+pub use recipient::{Identity, Recipient, Stanza};
+
+pub const NONCE_SIZE_BYTES: usize = 12;
+const SALT_SIZE_BYTES: usize = 16;
+pub const STREAM_ID_SIZE_BYTES: usize = 16;
+/// Fixed salt of the search key. Tag tokens must be the same across pointers,
+/// so the search key cannot be derived with a random salt like pointer keys.
+pub const SEARCH_KEY_SALT: &[u8] = b"en-crypt search key";
+
+/// KdfParams holds the salt and Argon2id cost parameters used to derive a
+/// symmetric key from a passphrase. The parameters are not secret and are
+/// stored in clear alongside the encrypted pointer, so that the same key can be
+/// derived again when the pointer is read.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub salt: Vec<u8>,
+    /// memory cost, in KiB
+    pub m_cost: u32,
+    /// number of iterations
+    pub t_cost: u32,
+    /// degree of parallelism
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams::new(
+            Params::DEFAULT_M_COST,
+            Params::DEFAULT_T_COST,
+            Params::DEFAULT_P_COST,
+        )
+    }
+}
+
+impl KdfParams {
+    /// Creates a new set of KDF parameters with a fresh random salt.
+    pub fn new(m_cost: u32, t_cost: u32, p_cost: u32) -> Self {
+        let mut salt = vec![0; SALT_SIZE_BYTES];
+        rand::thread_rng().fill_bytes(&mut salt);
+
+        KdfParams {
+            salt,
+            m_cost,
+            t_cost,
+            p_cost,
+        }
+    }
+
+    /// Returns the parameters used to derive the search key from a passphrase,
+    /// with the same costs and the fixed `SEARCH_KEY_SALT`.
+    pub fn for_search_key(&self) -> KdfParams {
+        KdfParams {
+            salt: SEARCH_KEY_SALT.to_vec(),
+            ..self.clone()
+        }
+    }
+
+    /// Checks that the cost parameters are accepted by Argon2id.
+    pub fn validate(&self) -> Result<(), Error> {
+        Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_SIZE_BYTES))
+            .map(|_| ())
+            .map_err(|e| Error::Kdf(e.to_string()))
+    }
+
+    /// Derives a 256 bit key from a passphrase using Argon2id.
+    pub fn derive_key(&self, passphrase: &[u8]) -> Result<Key, Error> {
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_SIZE_BYTES))
+            .map_err(|e| Error::Kdf(e.to_string()))?;
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+        let mut key = Key::new([0; KEY_SIZE_BYTES]);
+        argon2
+            .hash_password_into(passphrase, &self.salt, key.as_mut_bytes())
+            .map_err(|e| Error::Kdf(e.to_string()))?;
+
+        Ok(key)
+    }
+}
+
+/// WrappedKey is the data key of a pointer encrypted with a key-encryption
+/// key, such as a key derived from a passphrase. The data key encrypts the
+/// pointer blocks, so the key-encryption key can be changed by wrapping the
+/// data key again, without re-encrypting the blocks. Data keys are always
+/// wrapped with AES-256-GCM, whatever the cipher of the pointer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WrappedKey {
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl WrappedKey {
+    /// Encrypts `key` with the key-encryption key `kek`.
+    pub fn wrap(kek: &Key, key: &Key) -> Result<WrappedKey, Error> {
+        let nonce = random_nonce();
+        let ciphertext = Cipher::Aes256Gcm
+            .seal(kek, &nonce, key.as_bytes(), &[])
+            .ok_or_else(|| Error::Encode("Error wrapping the data key".to_string()))?;
+
+        Ok(WrappedKey { nonce, ciphertext })
+    }
+
+    /// Decrypts the data key with the key-encryption key `kek`. Fails with
+    /// `Error::WrongKey` if `kek` is not the key it was wrapped with.
+    pub fn unwrap(&self, kek: &Key) -> Result<Key, Error> {
+        if self.nonce.len() != NONCE_SIZE_BYTES {
+            return Err(Error::Decode(
+                "Wrapped key has an invalid nonce".to_string(),
+            ));
+        }
+
+        let key = Cipher::Aes256Gcm
+            .open(kek, &self.nonce, &self.ciphertext, &[])
+            .map(Zeroizing::new)
+            .ok_or(Error::WrongKey)?;
+
+        Key::from_slice(&key)
+            .ok_or_else(|| Error::Decode("Wrapped key has an invalid length".to_string()))
+    }
+}
+
+/// Generates a random 96 bit nonce, as used by AES-256-GCM. Nonces are never
+/// derived from the data being encrypted, so that a nonce is not reused across
+/// messages under the same key. See `Cipher::random_nonce` for the nonces of
+/// other ciphers.
+pub fn random_nonce() -> Vec<u8> {
+    random_bytes(NONCE_SIZE_BYTES)
+}
+
+/// Generates a random key, such as the data key of a pointer encrypted to
+/// recipients.
+pub fn random_key() -> Key {
+    let mut key = Key::new([0; KEY_SIZE_BYTES]);
+    rand::thread_rng().fill_bytes(key.as_mut_bytes());
+    key
+}
+
+/// Generates a random 128 bit identifier for the blocks of a pointer, which
+/// binds each block to the pointer it belongs to.
+pub fn random_stream_id() -> Vec<u8> {
+    random_bytes(STREAM_ID_SIZE_BYTES)
+}
+
+/// Derives the key of a block in convergent encryption mode from the block
+/// plaintext, so that the same block always encrypts to the same ciphertext.
+/// Anyone holding a block can then derive its key, which allows deduplication
+/// across users but reveals whether a stored pointer contains a known block.
+pub fn convergent_key(data: &[u8]) -> Key {
+    let mut hasher = Sha256::new();
+    hasher.update(b"en-crypt convergent block key");
+    hasher.update(data);
+    Key::new(hasher.finalize().into())
+}
+
+/// Derives the key the blocks of a pointer are encrypted with from its data
+/// key. Every key derived from the data key or the search key is used for a
+/// single purpose, so that one can be handed out without exposing what the
+/// others protect.
+pub fn blocks_key(data_key: &Key) -> Key {
+    data_key.derive_subkey(b"en-crypt blocks key")
+}
+
+/// Derives the key the manifest of a pointer, which holds its metadata, is
+/// encrypted with from its data key. In convergent mode the manifest also
+/// holds the block keys, so the metadata key then gives access to the blocks.
+pub fn metadata_key(data_key: &Key) -> Key {
+    data_key.derive_subkey(b"en-crypt metadata key")
+}
+
+/// Derives the key tag tokens are computed with from the search key. It is a
+/// search-only capability: it matches tags against searchable pointers, but
+/// neither decrypts them nor the tag index.
+pub fn tag_tokens_key(search_key: &Key) -> Key {
+    search_key.derive_subkey(b"en-crypt tag tokens key")
+}
+
+/// Computes the blind index token of a tag, HMAC-SHA256 of the tag under the
+/// tag tokens key, hex encoded. The same tag always has the same token, so a
+/// backend can match tokens without learning the tags nor the data key.
+pub fn tag_token(tokens_key: &Key, tag: &str) -> String {
+    to_hex(&hmac_sha256(tokens_key, tag.as_bytes()))
+}
+
+/// Computes the membership token of the pointers searchable under the tag
+/// tokens key, which they hold next to their tag tokens, so that they can be
+/// found even without any tag. It cannot collide with the token of a tag, since
+/// tags are UTF-8 and the 0xff byte it starts with never is.
+pub fn member_token(tokens_key: &Key) -> String {
+    to_hex(&hmac_sha256(tokens_key, b"\xffen-crypt searchable pointer"))
+}
+
+/// Derives the key the tag index is encrypted with from the search key.
+pub fn index_key(search_key: &Key) -> Key {
+    search_key.derive_subkey(b"en-crypt tag index key")
+}
+
+/// Name of the backend reference to the tag index of a search key, a MAC
+/// under a key derived from the search key, so that several search keys can
+/// have an index in the same backend without revealing which index belongs to
+/// which key.
+pub fn index_ref(search_key: &Key) -> String {
+    let mac_key = search_key.derive_subkey(b"en-crypt tag index mac key");
+    let name = hmac_sha256(&mac_key, b"en-crypt tag index name");
+    format!("tagindex{}", to_hex(&name[..16]))
+}
+
+/// Computes the key commitment of an encrypted pointer, HMAC-SHA256 of the
+/// pointer stream id under a key derived from its data key. AEAD ciphers do
+/// not commit to their key, so a ciphertext can be crafted to decrypt validly
+/// under two keys to two different plaintexts. No two keys have the same
+/// commitment, so a pointer only ever decrypts under the key it commits to.
+/// The stream id keeps the commitments of pointers sharing a key unlinkable.
+pub fn key_commitment(key: &Key, stream_id: &[u8]) -> Vec<u8> {
+    key_commitment_mac(key, stream_id)
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+/// Checks in constant time that `commitment` is the key commitment of `key`.
+pub fn verify_key_commitment(key: &Key, stream_id: &[u8], commitment: &[u8]) -> bool {
+    key_commitment_mac(key, stream_id)
+        .verify_slice(commitment)
+        .is_ok()
+}
+
+fn key_commitment_mac(key: &Key, stream_id: &[u8]) -> Hmac<Sha256> {
+    let commitment_key = key.derive_subkey(b"en-crypt key commitment key");
+    let mut mac = Hmac::<Sha256>::new_from_slice(commitment_key.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(stream_id);
+    mac
+}
+
+/// Derives a key from the input key material `ikm` with HKDF-SHA256, bound
+/// to the purpose named by `info`.
+pub fn hkdf(ikm: &[u8], salt: &[u8], info: &[u8]) -> Key {
+    let mut key = Key::new([0; KEY_SIZE_BYTES]);
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(info, key.as_mut_bytes())
+        .expect("HKDF can expand 32 bytes");
+    key
+}
+
+fn hmac_sha256(key: &Key, data: &[u8]) -> [u8; 32] {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn derive_key() {
+        let params = KdfParams::new(64, 1, 1);
+        let k1 = params.derive_key(b"correct horse battery staple").unwrap();
+        let k2 = params.derive_key(b"correct horse battery staple").unwrap();
+        assert_eq!(k1, k2);
+
+        let k3 = params.derive_key(b"correct horse battery stapler").unwrap();
+        assert_ne!(k1, k3);
+
+        // same passphrase, different salt
+        let other_params = KdfParams::new(64, 1, 1);
+        assert_ne!(params.salt, other_params.salt);
+        let k4 = other_params
+            .derive_key(b"correct horse battery staple")
+            .unwrap();
+        assert_ne!(k1, k4);
+    }
+
+    #[test]
+    fn random_nonce_unique() {
+        let n1 = random_nonce();
+        let n2 = random_nonce();
+        assert_eq!(n1.len(), NONCE_SIZE_BYTES);
+        assert_ne!(n1, n2);
+    }
+
+    #[test]
+    fn convergent_keys() {
+        assert_eq!(convergent_key(b"block"), convergent_key(b"block"));
+        assert_ne!(convergent_key(b"block"), convergent_key(b"another block"));
+    }
+
+    #[test]
+    fn tag_tokens() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let other_key = &Key::from(b"another very very secret key....");
+
+        let token = tag_token(key, "invoice");
+        assert_eq!(token.len(), 64);
+        assert_eq!(token, tag_token(key, "invoice"));
+        assert_ne!(token, tag_token(key, "receipt"));
+        assert_ne!(token, tag_token(other_key, "invoice"));
+        assert!(!token.contains("invoice"));
+
+        // the index key and reference do not reveal the search key
+        assert_ne!(&index_key(key), key);
+        assert_ne!(index_ref(key), index_ref(other_key));
+        assert!(index_ref(key).chars().all(|c| c.is_ascii_alphanumeric()));
+
+        // the search key salt does not depend on the pointer salt
+        let params = KdfParams::new(64, 1, 1).for_search_key();
+        assert_eq!(params, KdfParams::new(64, 1, 1).for_search_key());
+    }
+
+    #[test]
+    fn key_hierarchy() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+
+        let keys = [
+            key.clone(),
+            blocks_key(key),
+            metadata_key(key),
+            tag_tokens_key(key),
+            index_key(key),
+        ];
+        for (i, a) in keys.iter().enumerate() {
+            for b in &keys[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+        assert_eq!(blocks_key(key), blocks_key(key));
+        assert_ne!(
+            blocks_key(key),
+            blocks_key(&Key::from(b"another very very secret key...."))
+        );
+
+        // the tokens key does not give the index reference away
+        assert_ne!(index_ref(key), index_ref(&tag_tokens_key(key)));
+    }
+
+    #[test]
+    fn invalid_params() {
+        let params = KdfParams::new(0, 0, 0);
+        assert!(params.derive_key(b"passphrase").is_err());
+    }
+
+    #[test]
+    fn wrapped_key() {
+        let kek = &Key::from(b"hello darkness my good ol friend");
+        let key = random_key();
+
+        let wrapped = WrappedKey::wrap(kek, &key).unwrap();
+        assert_eq!(wrapped.unwrap(kek).unwrap(), key);
+        assert_ne!(wrapped, WrappedKey::wrap(kek, &key).unwrap());
+        assert!(matches!(
+            wrapped.unwrap(&Key::from(b"another very very secret key....")),
+            Err(Error::WrongKey)
+        ));
+    }
+
+    #[test]
+    fn key_commitments() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let other_key = &Key::from(b"another very very secret key....");
+        let stream_id = random_stream_id();
+
+        let commitment = key_commitment(key, &stream_id);
+        assert_eq!(commitment.len(), 32);
+        assert!(verify_key_commitment(key, &stream_id, &commitment));
+        assert!(!verify_key_commitment(other_key, &stream_id, &commitment));
+        assert!(!verify_key_commitment(
+            key,
+            &random_stream_id(),
+            &commitment
+        ));
+        assert!(!verify_key_commitment(key, &stream_id, &commitment[..16]));
+        assert!(!verify_key_commitment(key, &stream_id, &[]));
+
+        // pointers sharing a key do not share a commitment
+        assert_ne!(commitment, key_commitment(key, &random_stream_id()));
+    }
+
+    /// Multiplies two elements of GF(2^128) in the bit order of GHASH.
+    fn gf_mul(x: u128, y: u128) -> u128 {
+        let mut z = 0;
+        let mut v = y;
+        for i in 0..128 {
+            if x & (1 << (127 - i)) != 0 {
+                z ^= v;
+            }
+            v = if v & 1 != 0 {
+                (v >> 1) ^ (0xe1 << 120)
+            } else {
+                v >> 1
+            };
+        }
+        z
+    }
+
+    /// Inverts an element of GF(2^128), as x^(2^128 - 2).
+    fn gf_inv(x: u128) -> u128 {
+        let (mut inv, mut base) = (1 << 127, x);
+        for i in 0..128 {
+            if i != 0 {
+                inv = gf_mul(inv, base);
+            }
+            base = gf_mul(base, base);
+        }
+        inv
+    }
+
+    /// Encrypts a single block with AES-256.
+    fn aes256(key: &Key, block: [u8; 16]) -> u128 {
+        use aes_gcm::aes::cipher::{BlockEncrypt, KeyInit};
+
+        let mut block = block.into();
+        aes_gcm::aes::Aes256::new(key.as_bytes().into()).encrypt_block(&mut block);
+        u128::from_be_bytes(block.into())
+    }
+
+    /// Computes GHASH of the associated data `ad` and the ciphertext blocks
+    /// `ctext` under the hash key `h`.
+    fn ghash(h: u128, ad: &[u8], ctext: &[u128]) -> u128 {
+        let mut blocks: Vec<u128> = ad
+            .chunks(16)
+            .map(|chunk| {
+                let mut block = [0; 16];
+                block[..chunk.len()].copy_from_slice(chunk);
+                u128::from_be_bytes(block)
+            })
+            .collect();
+        blocks.extend_from_slice(ctext);
+        blocks.push(((ad.len() as u128 * 8) << 64) | (ctext.len() as u128 * 128));
+        blocks.iter().fold(0, |y, x| gf_mul(y ^ x, h))
+    }
+
+    /// Returns a two block AES-256-GCM ciphertext which authenticates under
+    /// both `k1` and `k2`, with the same nonce and associated data.
+    ///
+    /// AES-256-GCM does not commit to its key: with hash keys H1 and H2, the
+    /// tag is G + C2*H^2 + E(J0) under each key, where G is the GHASH of the
+    /// ciphertext with C2 = 0, so both tags are equal for
+    /// C2 = (G1 + G2 + E1(J0) + E2(J0)) / (H1^2 + H2^2)
+    pub(crate) fn salamander(k1: &Key, k2: &Key, nonce: &[u8], ad: &[u8]) -> Vec<u8> {
+        let mut j0 = [0_u8; 16];
+        j0[..NONCE_SIZE_BYTES].copy_from_slice(nonce);
+        j0[15] = 1;
+
+        let (h1, h2) = (aes256(k1, [0; 16]), aes256(k2, [0; 16]));
+        let (e1, e2) = (aes256(k1, j0), aes256(k2, j0));
+        let c1 = u128::from_be_bytes(*b"en-crypt invisib");
+        let (g1, g2) = (ghash(h1, ad, &[c1, 0]), ghash(h2, ad, &[c1, 0]));
+        let c2 = gf_mul(g1 ^ g2 ^ e1 ^ e2, gf_inv(gf_mul(h1, h1) ^ gf_mul(h2, h2)));
+        let tag = ghash(h1, ad, &[c1, c2]) ^ e1;
+
+        let mut ctext = vec![];
+        for block in [c1, c2, tag] {
+            ctext.extend_from_slice(&block.to_be_bytes());
+        }
+        ctext
+    }
+
+    #[test]
+    fn invisible_salamanders() {
+        // a ciphertext which decrypts to different messages under two keys,
+        // see `salamander`
+        let k1 = &Key::from(b"hello darkness my good ol friend");
+        let k2 = &Key::from(b"another very very secret key....");
+        let nonce = [7_u8; 12];
+        for ad in [&b""[..], b"with some associated data"] {
+            let ctext = salamander(k1, k2, &nonce, ad);
+            let m1 = Cipher::Aes256Gcm.open(k1, &nonce, &ctext, ad).unwrap();
+            let m2 = Cipher::Aes256Gcm.open(k2, &nonce, &ctext, ad).unwrap();
+            assert_ne!(m1, m2);
+        }
+
+        // the key commitment tells the two keys apart
+        let stream_id = random_stream_id();
+        let commitment = key_commitment(k1, &stream_id);
+        assert_ne!(commitment, key_commitment(k2, &stream_id));
+        assert!(verify_key_commitment(k1, &stream_id, &commitment));
+        assert!(!verify_key_commitment(k2, &stream_id, &commitment));
+    }
+}
@@ -0,0 +1,228 @@
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use super::{hkdf, to_hex, Cipher, Key};
+use crate::error::Error;
+
+/// Prefix of the text encoding of a `Recipient`
+const RECIPIENT_PREFIX: &str = "enpub";
+/// Prefix of the text encoding of an `Identity`
+const IDENTITY_PREFIX: &str = "ensec";
+const STANZA_INFO: &[u8] = b"en-crypt x25519 stanza";
+
+/// Recipient is the X25519 public key a pointer can be encrypted to, encoded
+/// as `enpub` followed by the hex encoded key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recipient(PublicKey);
+
+/// Identity is the X25519 secret key of a recipient, which decrypts the
+/// pointers encrypted to it. It is encoded as `ensec` followed by the hex
+/// encoded key, and not printed by `Debug`.
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+/// Stanza holds the data key of a pointer wrapped to a single recipient: the
+/// key is encrypted with AES-256-GCM under a key derived from an X25519
+/// exchange between an ephemeral key and the recipient key, as in age.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stanza {
+    /// Ephemeral public key
+    pub ephemeral: Vec<u8>,
+    /// Encrypted data key
+    pub wrapped_key: Vec<u8>,
+}
+
+impl Recipient {
+    /// Wraps `key` to the recipient, with a fresh ephemeral key.
+    pub fn wrap_key(&self, key: &Key) -> Result<Stanza, Error> {
+        let ephemeral = StaticSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(&self.0);
+        if !shared.was_contributory() {
+            return Err(Error::InvalidArgument(
+                "Recipient is not a valid X25519 public key".to_string(),
+            ));
+        }
+
+        let wrapping_key = stanza_key(shared.as_bytes(), &ephemeral_public, &self.0);
+        // every wrapping key encrypts a single data key
+        let cipher = Cipher::Aes256Gcm;
+        let wrapped_key = cipher
+            .seal(&wrapping_key, &cipher.zero_nonce(), key.as_bytes(), &[])
+            .ok_or_else(|| Error::Encode("Error wrapping the data key".to_string()))?;
+
+        Ok(Stanza {
+            ephemeral: ephemeral_public.as_bytes().to_vec(),
+            wrapped_key,
+        })
+    }
+}
+
+impl Identity {
+    /// Generates a new random identity.
+    pub fn generate() -> Self {
+        Identity(StaticSecret::random_from_rng(OsRng))
+    }
+
+    /// Public key of the identity, which pointers are encrypted to.
+    pub fn recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0))
+    }
+
+    /// Unwraps the data key from the stanza wrapped to this identity. Fails
+    /// with `Error::WrongKey` if no stanza is.
+    pub fn unwrap_key(&self, stanzas: &[Stanza]) -> Result<Key, Error> {
+        let public = PublicKey::from(&self.0);
+        for stanza in stanzas {
+            let ephemeral: [u8; 32] = match stanza.ephemeral.as_slice().try_into() {
+                Ok(ephemeral) => ephemeral,
+                Err(_) => continue,
+            };
+            let ephemeral = PublicKey::from(ephemeral);
+            let shared = self.0.diffie_hellman(&ephemeral);
+            if !shared.was_contributory() {
+                continue;
+            }
+
+            let wrapping_key = stanza_key(shared.as_bytes(), &ephemeral, &public);
+            let cipher = Cipher::Aes256Gcm;
+            if let Some(key) = cipher.open(
+                &wrapping_key,
+                &cipher.zero_nonce(),
+                &stanza.wrapped_key,
+                &[],
+            ) {
+                if let Some(key) = Key::from_slice(&Zeroizing::new(key)) {
+                    return Ok(key);
+                }
+            }
+        }
+
+        Err(Error::WrongKey)
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", RECIPIENT_PREFIX, to_hex(self.0.as_bytes()))
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", IDENTITY_PREFIX, to_hex(self.0.as_bytes()))
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Identity({})", self.recipient())
+    }
+}
+
+impl FromStr for Recipient {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let key = parse_key(s, RECIPIENT_PREFIX)
+            .ok_or_else(|| Error::InvalidArgument(format!("Invalid recipient: {}", s)))?;
+        Ok(Recipient(PublicKey::from(key)))
+    }
+}
+
+impl FromStr for Identity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        // the key is not included in the error, so that it is not printed
+        let key = parse_key(s, IDENTITY_PREFIX)
+            .ok_or_else(|| Error::InvalidArgument("Invalid identity".to_string()))?;
+        Ok(Identity(StaticSecret::from(key)))
+    }
+}
+
+/// Derives the key a stanza is encrypted with from the X25519 shared secret,
+/// bound to both public keys of the exchange.
+fn stanza_key(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> Key {
+    let mut salt = ephemeral.as_bytes().to_vec();
+    salt.extend_from_slice(recipient.as_bytes());
+
+    hkdf(shared, &salt, STANZA_INFO)
+}
+
+fn parse_key(s: &str, prefix: &str) -> Option<[u8; 32]> {
+    let hex = s.trim().strip_prefix(prefix)?;
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_unwrap() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let mallory = Identity::generate();
+
+        let stanzas = vec![
+            alice.recipient().wrap_key(key).unwrap(),
+            bob.recipient().wrap_key(key).unwrap(),
+        ];
+        assert_eq!(&alice.unwrap_key(&stanzas).unwrap(), key);
+        assert_eq!(&bob.unwrap_key(&stanzas).unwrap(), key);
+        assert!(matches!(mallory.unwrap_key(&stanzas), Err(Error::WrongKey)));
+
+        // each stanza has its own ephemeral key
+        assert_ne!(stanzas[0].ephemeral, stanzas[1].ephemeral);
+        let again = alice.recipient().wrap_key(key).unwrap();
+        assert_ne!(again.wrapped_key, stanzas[0].wrapped_key);
+
+        // a tampered stanza does not unwrap
+        let mut tampered = stanzas[..1].to_vec();
+        tampered[0].wrapped_key[0] ^= 1;
+        assert!(alice.unwrap_key(&tampered).is_err());
+    }
+
+    #[test]
+    fn encoding() {
+        let identity = Identity::generate();
+        let recipient = identity.recipient();
+
+        let encoded = recipient.to_string();
+        assert!(encoded.starts_with("enpub"));
+        assert_eq!(encoded.parse::<Recipient>().unwrap(), recipient);
+
+        let encoded = identity.to_string();
+        assert!(encoded.starts_with("ensec"));
+        let parsed: Identity = encoded.parse().unwrap();
+        assert_eq!(parsed.recipient(), recipient);
+        // the secret key is not printed
+        assert!(!format!("{:?}", identity).contains(&encoded));
+
+        assert!("enpub1234".parse::<Recipient>().is_err());
+        assert!(recipient.to_string().parse::<Identity>().is_err());
+    }
+
+    #[test]
+    fn low_order_recipient() {
+        let recipient: Recipient = format!("enpub{}", "00".repeat(32)).parse().unwrap();
+        assert!(matches!(
+            recipient.wrap_key(&Key::from([0; 32])),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+
+/// Error is the error type returned by all en-crypt operations.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing local data failed
+    Io(std::io::Error),
+    /// Data could not be serialised
+    Encode(String),
+    /// Data could not be deserialised, e.g. an object is not a valid pointer
+    Decode(String),
+    /// A ciphertext failed authentication. Either it was tampered with or it
+    /// was encrypted with a different key
+    Authentication(String),
+    /// The key does not decrypt the pointer
+    WrongKey,
+    /// A block does not have the expected content
+    CorruptBlock(String),
+    /// Data does not hash to the CID of the block or pointer it was read as.
+    /// Holds the offending CID
+    IntegrityMismatch(String),
+    /// An object referenced by a pointer is not in the backend
+    MissingBlock(String),
+    /// The storage backend failed
+    Backend(String),
+    /// The configuration is not valid
+    Config(String),
+    /// Key derivation failed, e.g. because of invalid KDF parameters
+    Kdf(String),
+    /// An argument or parameter is not valid
+    InvalidArgument(String),
+}
+
+impl Error {
+    /// Exit code of the CLI for the error:
+    ///
+    /// - 1: invalid argument
+    /// - 2: invalid configuration
+    /// - 3: I/O error
+    /// - 4: backend error
+    /// - 5: missing block or object
+    /// - 6: wrong key or failed authentication
+    /// - 7: corrupt or undecodable data
+    /// - 8: key derivation error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidArgument(_) => 1,
+            Error::Config(_) => 2,
+            Error::Io(_) => 3,
+            Error::Backend(_) => 4,
+            Error::MissingBlock(_) => 5,
+            Error::WrongKey | Error::Authentication(_) => 6,
+            Error::Encode(_)
+            | Error::Decode(_)
+            | Error::CorruptBlock(_)
+            | Error::IntegrityMismatch(_) => 7,
+            Error::Kdf(_) => 8,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Encode(e) => write!(f, "Encoding error: {}", e),
+            Error::Decode(e) => write!(f, "Decoding error: {}", e),
+            Error::Authentication(e) => write!(f, "Authentication failed: {}", e),
+            Error::WrongKey => write!(f, "Wrong key: the pointer cannot be decrypted"),
+            Error::CorruptBlock(e) => write!(f, "Corrupt block: {}", e),
+            Error::IntegrityMismatch(cid) => {
+                write!(
+                    f,
+                    "Integrity check failed: {} does not match its content",
+                    cid
+                )
+            }
+            Error::MissingBlock(cid) => write!(f, "Object {} not found in backend", cid),
+            Error::Backend(e) => write!(f, "Backend error: {}", e),
+            Error::Config(e) => write!(f, "Invalid config: {}", e),
+            Error::Kdf(e) => write!(f, "Key derivation error: {}", e),
+            Error::InvalidArgument(e) => write!(f, "Invalid argument: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    /// Unwraps errors which were wrapped in an `std::io::Error` to cross a
+    /// `Read` or `Write` implementation.
+    fn from(e: std::io::Error) -> Self {
+        if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            if let Some(inner) = e.into_inner() {
+                if let Ok(err) = inner.downcast::<Error>() {
+                    return *err;
+                }
+            }
+            unreachable!();
+        }
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            e => std::io::Error::other(e),
+        }
+    }
+}
+
+impl From<serde_cbor::Error> for Error {
+    fn from(e: serde_cbor::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_error_roundtrip() {
+        let io_err: std::io::Error = Error::MissingBlock("cid".to_string()).into();
+        match Error::from(io_err) {
+            Error::MissingBlock(cid) => assert_eq!(cid, "cid"),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let io_err = std::io::Error::new(std::io::ErrorKind::NotFound, "not found");
+        let err = Error::from(io_err);
+        assert!(matches!(err, Error::Io(_)));
+        assert_eq!(err.exit_code(), 3);
+    }
+}
@@ -0,0 +1,37 @@
+//! en-crypt stores, searches and recovers encrypted data.
+//!
+//! Data is split in blocks by a [`Chunker`], encrypted and linked together by
+//! a [`Pointer`]. Blocks and pointers are stored in a [`Backend`] as opaque
+//! objects addressed by their CID.
+//!
+//! ```
+//! use en_crypt::{KdfParams, MemoryBackend, Pointer};
+//! use std::io::Read;
+//!
+//! let mut backend = MemoryBackend::new();
+//! let params = KdfParams::new(64, 1, 1);
+//! let mut pointer = Pointer::from(b"hello world")?.encrypt_with_passphrase("passphrase", params)?;
+//! let root = pointer.store(&mut backend)?;
+//!
+//! let header = Pointer::load_header(&root, &backend)?;
+//! let key = header.derive_key("passphrase")?;
+//! let mut data = vec![];
+//! header.reader(&key, &backend)?.read_to_end(&mut data)?;
+//! assert_eq!(data, b"hello world");
+//! # Ok::<(), en_crypt::Error>(())
+//! ```
+
+pub mod backend;
+pub mod crypto;
+pub mod error;
+pub mod structs;
+
+pub use backend::{Backend, FsBackend, MemoryBackend, MirrorBackend};
+pub use crypto::{
+    tag_token, tag_tokens_key, Cipher, Identity, KdfParams, Key, Recipient, Stanza, WrappedKey,
+};
+pub use error::Error;
+pub use structs::{
+    cid_of, Block, Chunker, Cmp, Fields, Match, Metadata, Pointer, PointerBuilder, PointerReader,
+    Query, TagIndex, Tags, Term, Value,
+};
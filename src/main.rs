@@ -1,9 +1,122 @@
-mod crypto;
-mod structs;
-
 mod commands;
+mod config;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::io::Write;
+
+use commands::Secret;
+use config::{BackendConfig, Config, OutputFormat};
+use en_crypt::{Error, Query, Recipient};
+
+/// Loads the config file and applies the command line overrides.
+fn load_config(matches: &ArgMatches) -> Result<Config, Error> {
+    let mut config = Config::load(matches.value_of("config"))?;
 
-use clap::{App, Arg, SubCommand};
+    if let Some(path) = matches.value_of("backend") {
+        config.backends = vec![BackendConfig::Fs { path: path.into() }];
+    }
+
+    if let Some(size) = matches.value_of("block-size") {
+        config.chunk_size = size
+            .parse()
+            .map_err(|_| Error::InvalidArgument(format!("Invalid block size: {}", size)))?;
+        config.chunker = None;
+        config.chunker().validate()?;
+    }
+
+    if matches.is_present("convergent") {
+        config.convergent = true;
+    }
+
+    if let Some(cipher) = matches.value_of("cipher") {
+        config.cipher = cipher.parse()?;
+    }
+
+    Ok(config)
+}
+
+/// Returns the passphrase passed in the command line or, if not set, the one
+/// read from the key source in the config.
+fn read_key(matches: &ArgMatches, config: &Config) -> Result<String, Error> {
+    match optional_key(matches, config)? {
+        Some(key) => Ok(key),
+        None => Err(Error::InvalidArgument(
+            "Key (-k) param not provided".to_string(),
+        )),
+    }
+}
+
+fn optional_key(matches: &ArgMatches, config: &Config) -> Result<Option<String>, Error> {
+    match matches.value_of("key") {
+        Some(key) => Ok(Some(key.to_string())),
+        None => config.read_key(),
+    }
+}
+
+/// Returns the identity file passed in the command line or, if not set, the
+/// passphrase.
+fn read_secret(matches: &ArgMatches, config: &Config) -> Result<Secret, Error> {
+    match matches.value_of("identity") {
+        Some(path) => Ok(Secret::Identity(commands::read_identity_file(path)?)),
+        None => Ok(Secret::Passphrase(read_key(matches, config)?)),
+    }
+}
+
+/// Returns the search passphrase passed in the command line or, if not set,
+/// the one read from the search key source in the config.
+fn read_search_key(matches: &ArgMatches, config: &Config) -> Result<Option<String>, Error> {
+    match matches.value_of("search-key") {
+        Some(key) => Ok(Some(key.to_string())),
+        None => config.read_search_key(),
+    }
+}
+
+/// Returns the values of an argument which can be repeated.
+fn values(matches: &ArgMatches, name: &str) -> Vec<String> {
+    matches
+        .values_of(name)
+        .map(|values| values.map(|v| v.to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// Returns the search query, combined with the `--tag` and `--and` terms,
+/// then the `--or` and the `--not` terms, so that they are evaluated as
+/// `(query and tags and ands) or ors, and not nots`. The tags of the flags are
+/// matched literally.
+fn search_query(matches: &ArgMatches) -> Result<Query, Error> {
+    let mut query = Query::parse(matches.value_of("QUERY").unwrap_or_default())?;
+    for tag in values(matches, "tag").iter().chain(&values(matches, "and")) {
+        query = query.and(Query::tag(tag));
+    }
+    for tag in values(matches, "or") {
+        query = match query {
+            Query::All => Query::tag(&tag),
+            query => query.or(Query::tag(&tag)),
+        };
+    }
+    for tag in values(matches, "not") {
+        query = query.and(Query::tag(&tag).not());
+    }
+    Ok(query)
+}
+
+/// Warns on stderr about files skipped because they were stored with an
+/// earlier format of en-crypt.
+fn warn_unsupported(count: usize) {
+    if count > 0 {
+        eprintln!(
+            "Warning: skipped {} file(s) stored with an earlier format of en-crypt, which must be stored again",
+            count
+        );
+    }
+}
+
+/// Returns the value of a required argument.
+fn required<'a>(matches: &'a ArgMatches, name: &str, flag: &str) -> Result<&'a str, Error> {
+    matches
+        .value_of(name)
+        .ok_or_else(|| Error::InvalidArgument(format!("{} param not provided", flag)))
+}
 
 fn main() {
     let matches = App::new("en-crypt")
@@ -19,7 +132,7 @@ fn main() {
                         .short("f")
                         .value_name("FILE")
                         .takes_value(true)
-                        .help("path to file to store"),
+                        .help("path to file to store, or - to read from stdin"),
                 )
                 .arg(
                     Arg::with_name("key")
@@ -27,26 +140,302 @@ fn main() {
                         .short("k")
                         .value_name("KEY")
                         .takes_value(true)
-                        .help("secret key to encrypt/decrypt data"),
+                        .help("passphrase used to derive the encryption key"),
+                )
+                .arg(
+                    Arg::with_name("recipient")
+                        .long("recipient")
+                        .short("r")
+                        .value_name("PUBLIC KEY")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("public key to encrypt the file to, instead of a passphrase. Can be repeated"),
                 )
                 .arg(
                     Arg::with_name("config")
                         .long("config")
                         .short("c")
                         .value_name("CONFIG")
+                        .takes_value(true)
                         .help("config file. Defaults to ~/.en-crypt/config.json"),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .short("b")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .help("local backend directory. Defaults to ~/.en-crypt/store"),
+                )
+                .arg(
+                    Arg::with_name("block-size")
+                        .long("block-size")
+                        .value_name("BYTES")
+                        .takes_value(true)
+                        .help("size of the blocks the file is split into. Defaults to 262144"),
+                )
+                .arg(
+                    Arg::with_name("cipher")
+                        .long("cipher")
+                        .value_name("CIPHER")
+                        .takes_value(true)
+                        .possible_values(&[
+                            "aes-256-gcm",
+                            "chacha20-poly1305",
+                            "xchacha20-poly1305",
+                            "aes-256-gcm-siv",
+                        ])
+                        .help("cipher the file is encrypted with. Defaults to aes-256-gcm"),
+                )
+                .arg(
+                    Arg::with_name("convergent")
+                        .long("convergent")
+                        .help("encrypts blocks with keys derived from their content, so that identical blocks are stored once. Reveals whether the file contains known blocks"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .short("t")
+                        .value_name("TAG")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("tag to attach to the file, can be repeated"),
+                )
+                .arg(
+                    Arg::with_name("search-key")
+                        .long("search-key")
+                        .value_name("KEY")
+                        .takes_value(true)
+                        .help("passphrase of the tag tokens, which make the file searchable without decrypting it"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("delete")
+                .about("deletes a stored file and its blocks")
+                .arg(
+                    Arg::with_name("cid")
+                        .long("cid")
+                        .value_name("CID")
+                        .takes_value(true)
+                        .help("root CID of the stored file"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .short("k")
+                        .value_name("KEY")
+                        .takes_value(true)
+                        .help("passphrase used to derive the encryption key"),
+                )
+                .arg(
+                    Arg::with_name("identity")
+                        .long("identity")
+                        .short("i")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("identity file of a recipient the file was stored for, instead of a passphrase"),
+                )
+                .arg(
+                    Arg::with_name("search-key")
+                        .long("search-key")
+                        .value_name("KEY")
+                        .takes_value(true)
+                        .help("passphrase of the tag index the file is removed from"),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .short("c")
+                        .value_name("CONFIG")
+                        .takes_value(true)
+                        .help("config file. Defaults to ~/.en-crypt/config.json"),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .short("b")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .help("local backend directory. Defaults to ~/.en-crypt/store"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("lists the stored files which match a query")
+                .arg(
+                    Arg::with_name("QUERY")
+                        .index(1)
+                        .help(
+                            "query over the file tags and metadata, such as \
+                             'invoice AND (year>=2025 OR draft) NOT size>10MB'",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .short("t")
+                        .value_name("TAG")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("tag the files must have"),
+                )
+                .arg(
+                    Arg::with_name("and")
+                        .long("and")
+                        .value_name("TAG")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("tag the files must also have"),
+                )
+                .arg(
+                    Arg::with_name("or")
+                        .long("or")
+                        .value_name("TAG")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("matches files with this tag, or matching the terms before it"),
+                )
+                .arg(
+                    Arg::with_name("not")
+                        .long("not")
+                        .value_name("TAG")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("tag the files must not have"),
+                )
+                .arg(
+                    Arg::with_name("search-key")
+                        .long("search-key")
+                        .value_name("KEY")
+                        .takes_value(true)
+                        .help("passphrase of the tag tokens. Matches files without decrypting them"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .short("k")
+                        .value_name("KEY")
+                        .takes_value(true)
+                        .help("passphrase used to derive the encryption key"),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .short("c")
+                        .value_name("CONFIG")
+                        .takes_value(true)
+                        .help("config file. Defaults to ~/.en-crypt/config.json"),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .short("b")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .help("local backend directory. Defaults to ~/.en-crypt/store"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("read")
                 .about("decrypts and reads encrypted blob")
+                .arg(
+                    Arg::with_name("cid")
+                        .long("cid")
+                        .value_name("CID")
+                        .takes_value(true)
+                        .help("root CID of the stored file"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .short("k")
+                        .value_name("KEY")
+                        .takes_value(true)
+                        .help("passphrase used to derive the encryption key"),
+                )
+                .arg(
+                    Arg::with_name("identity")
+                        .long("identity")
+                        .short("i")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("identity file of a recipient the file was stored for, instead of a passphrase"),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .short("c")
+                        .value_name("CONFIG")
+                        .takes_value(true)
+                        .help("config file. Defaults to ~/.en-crypt/config.json"),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .short("b")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .help("local backend directory. Defaults to ~/.en-crypt/store"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rekey")
+                .about("changes the passphrase of the stored files, without re-encrypting them")
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .short("k")
+                        .value_name("KEY")
+                        .takes_value(true)
+                        .help("passphrase the files were stored with"),
+                )
+                .arg(
+                    Arg::with_name("new-key")
+                        .long("new-key")
+                        .value_name("KEY")
+                        .takes_value(true)
+                        .help("new passphrase of the files"),
+                )
+                .arg(
+                    Arg::with_name("search-key")
+                        .long("search-key")
+                        .value_name("KEY")
+                        .takes_value(true)
+                        .help("passphrase of the tag index the new CIDs are written to"),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .short("c")
+                        .value_name("CONFIG")
+                        .takes_value(true)
+                        .help("config file. Defaults to ~/.en-crypt/config.json"),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .short("b")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .help("local backend directory. Defaults to ~/.en-crypt/store"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("stores again a file whose pointer was written by the first version of en-crypt")
                 .arg(
                     Arg::with_name("file")
                         .long("file")
                         .short("f")
                         .value_name("FILE")
                         .takes_value(true)
-                        .help("path to file to store"),
+                        .help("path to the legacy pointer"),
                 )
                 .arg(
                     Arg::with_name("key")
@@ -54,14 +443,35 @@ fn main() {
                         .short("k")
                         .value_name("KEY")
                         .takes_value(true)
-                        .help("secret key to encrypt/decrypt data"),
+                        .help("passphrase the file was stored with, and is stored again with"),
                 )
                 .arg(
                     Arg::with_name("config")
                         .long("config")
                         .short("c")
                         .value_name("CONFIG")
+                        .takes_value(true)
                         .help("config file. Defaults to ~/.en-crypt/config.json"),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .short("b")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .help("local backend directory. Defaults to ~/.en-crypt/store"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("keygen")
+                .about("generates an identity, whose public key files can be stored for")
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("file to write the identity to. Defaults to stdout"),
                 ),
         )
         .get_matches();
@@ -71,47 +481,153 @@ fn main() {
         println!("{}", matches.usage.clone().unwrap());
     }
 
+    if let Err(err) = run(&matches) {
+        eprintln!("Error: {}", err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
     // Handles store command
     if let Some(matches) = matches.subcommand_matches("store") {
-        let file_path = match matches.value_of("file") {
-            Some(f) => f,
-            None => {
-                println!("File (-f) param not provided");
-                std::process::exit(0);
-            }
-        };
+        let file_path = required(matches, "file", "File (-f)")?;
 
-        let key = match matches.value_of("key") {
-            Some(f) => f,
-            None => {
-                println!("Key (-k) param not provided");
-                std::process::exit(0);
-            }
+        let config = load_config(matches)?;
+        let recipients = values(matches, "recipient")
+            .iter()
+            .map(|r| r.parse())
+            .collect::<Result<Vec<Recipient>, Error>>()?;
+        // with recipients, only an explicit key is passed on, to be rejected
+        let key = match recipients.is_empty() {
+            true => Some(read_key(matches, &config)?),
+            false => matches.value_of("key").map(|k| k.to_string()),
         };
 
-        let encrypted_raw = commands::handle_store_command(file_path, key);
-        println!("{:?}", encrypted_raw);
+        let tags = values(matches, "tag");
+        let search_key = read_search_key(matches, &config)?;
+        let cid = commands::handle_store_command(
+            file_path,
+            key.as_deref(),
+            &recipients,
+            search_key.as_deref(),
+            tags,
+            &config,
+        )?;
+        match config.output {
+            OutputFormat::Text => println!("{}", cid),
+            OutputFormat::Json => println!("{}", serde_json::json!({ "cid": cid })),
+        }
     }
 
     // Handles read command
     if let Some(matches) = matches.subcommand_matches("read") {
-        let file_path = match matches.value_of("file") {
-            Some(f) => f,
-            None => {
-                println!("File (-f) param not provided");
-                std::process::exit(0);
-            }
-        };
+        let cid = required(matches, "cid", "CID (--cid)")?;
+
+        let config = load_config(matches)?;
+        let secret = read_secret(matches, &config)?;
+
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        commands::handle_read_command(cid, &secret, &config, &mut out)?;
+        out.flush()?;
+    }
+
+    // Handles delete command
+    if let Some(matches) = matches.subcommand_matches("delete") {
+        let cid = required(matches, "cid", "CID (--cid)")?;
+
+        let config = load_config(matches)?;
+        let secret = read_secret(matches, &config)?;
+        let search_key = read_search_key(matches, &config)?;
+
+        commands::handle_delete_command(cid, &secret, search_key.as_deref(), &config)?;
+    }
+
+    // Handles rekey command
+    if let Some(matches) = matches.subcommand_matches("rekey") {
+        let new_key = required(matches, "new-key", "New key (--new-key)")?;
+
+        let config = load_config(matches)?;
+        let key = read_key(matches, &config)?;
+        let search_key = read_search_key(matches, &config)?;
 
-        let key = match matches.value_of("key") {
-            Some(f) => f,
-            None => {
-                println!("Key (-k) param not provided");
-                std::process::exit(0);
+        let rekeyed =
+            commands::handle_rekey_command(&key, new_key, search_key.as_deref(), &config)?;
+        match config.output {
+            OutputFormat::Text => {
+                for (cid, new_cid) in &rekeyed.cids {
+                    println!("{}\t{}", cid, new_cid);
+                }
             }
-        };
+            OutputFormat::Json => {
+                let cids: Vec<_> = rekeyed
+                    .cids
+                    .iter()
+                    .map(|(cid, new_cid)| serde_json::json!({ "cid": cid, "new_cid": new_cid }))
+                    .collect();
+                println!("{}", serde_json::json!({ "rekeyed": cids }));
+            }
+        }
+        warn_unsupported(rekeyed.unsupported);
+    }
+
+    // Handles migrate command
+    if let Some(matches) = matches.subcommand_matches("migrate") {
+        let file_path = required(matches, "file", "File (-f)")?;
+
+        let config = load_config(matches)?;
+        let key = read_key(matches, &config)?;
+
+        let cid = commands::handle_migrate_command(file_path, &key, &config)?;
+        match config.output {
+            OutputFormat::Text => println!("{}", cid),
+            OutputFormat::Json => println!("{}", serde_json::json!({ "cid": cid })),
+        }
+    }
 
-        let raw = commands::handle_read_command(file_path, key);
-        println!("{:?}", raw);
+    // Handles keygen command
+    if let Some(matches) = matches.subcommand_matches("keygen") {
+        let output = matches.value_of("output");
+
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        let recipient = commands::handle_keygen_command(output, &mut out)?;
+        if output.is_some() {
+            println!("Public key: {}", recipient);
+        }
     }
+
+    // Handles search command
+    if let Some(matches) = matches.subcommand_matches("search") {
+        let config = load_config(matches)?;
+        let key = optional_key(matches, &config)?;
+        let search_key = read_search_key(matches, &config)?;
+
+        let search = commands::handle_search_command(
+            &search_query(matches)?,
+            key.as_deref(),
+            search_key.as_deref(),
+            &config,
+        )?;
+        let (results, unsupported) = (search.results, search.unsupported);
+        match config.output {
+            OutputFormat::Text => {
+                for r in results {
+                    let filename = r.filename.unwrap_or_else(|| "-".to_string());
+                    let size = r.size.map_or_else(|| "-".to_string(), |s| s.to_string());
+                    println!("{}\t{}\t{}", r.cid, filename, size);
+                }
+            }
+            OutputFormat::Json => {
+                let results: Vec<_> = results
+                    .into_iter()
+                    .map(|r| serde_json::json!({ "cid": r.cid, "filename": r.filename, "size": r.size }))
+                    .collect();
+                println!("{}", serde_json::Value::Array(results));
+            }
+        }
+        warn_unsupported(unsupported);
+    }
+
+    Ok(())
 }
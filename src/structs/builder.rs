@@ -0,0 +1,471 @@
+use std::io::Write;
+
+use super::{
+    block_ad, root_cid, search_tokens, store_block, wrap_for_recipients, Block, Chunker, Manifest,
+    Metadata, Pointer,
+};
+use crate::backend::Backend;
+use crate::crypto::{
+    blocks_key, random_key, random_stream_id, Cipher, KdfParams, Key, Recipient, Stanza, WrappedKey,
+};
+use crate::error::Error;
+
+/// PointerBuilder creates an encrypted pointer from data written to it
+/// incrementally. Each block is encrypted and stored in the backend once the
+/// block after it is cut, since only then is it known whether it is the last
+/// block. At most two blocks of plaintext are buffered regardless of the size
+/// of the data. `finish` stores the pointer and returns its root CID.
+pub struct PointerBuilder<'a> {
+    backend: &'a mut dyn Backend,
+    key: Key,
+    /// Key the blocks are encrypted with, derived from the data key
+    blocks_key: Key,
+    kdf: Option<KdfParams>,
+    wrapped_key: Option<WrappedKey>,
+    /// Data key wrapped to each recipient
+    recipients: Vec<Stanza>,
+    chunker: Chunker,
+    cipher: Cipher,
+    convergent: bool,
+    /// Key of the tag tokens of a searchable pointer
+    tokens_key: Option<Key>,
+    buf: Vec<u8>,
+    stream_id: Vec<u8>,
+    /// Last block cut, not encrypted yet
+    pending: Option<Block>,
+    links: Vec<String>,
+    manifest: Manifest,
+}
+
+impl<'a> PointerBuilder<'a> {
+    pub fn new(
+        key: &Key,
+        chunker: Chunker,
+        backend: &'a mut dyn Backend,
+    ) -> Result<PointerBuilder<'a>, Error> {
+        chunker.validate()?;
+
+        Ok(PointerBuilder {
+            backend,
+            key: key.clone(),
+            blocks_key: blocks_key(key),
+            kdf: None,
+            wrapped_key: None,
+            recipients: vec![],
+            chunker,
+            cipher: Cipher::default(),
+            convergent: false,
+            tokens_key: None,
+            buf: Vec::with_capacity(chunker.max_size()),
+            stream_id: random_stream_id(),
+            pending: None,
+            links: vec![],
+            manifest: Manifest::default(),
+        })
+    }
+
+    /// Creates a builder which encrypts with a random data key wrapped with
+    /// `kek`, as `Pointer::encrypt_with_kek` does.
+    pub fn with_kek(
+        kek: &Key,
+        chunker: Chunker,
+        backend: &'a mut dyn Backend,
+    ) -> Result<PointerBuilder<'a>, Error> {
+        let key = random_key();
+        let wrapped_key = WrappedKey::wrap(kek, &key)?;
+
+        let mut builder = PointerBuilder::new(&key, chunker, backend)?;
+        builder.wrapped_key = Some(wrapped_key);
+
+        Ok(builder)
+    }
+
+    /// Creates a builder which encrypts with a data key wrapped with a key
+    /// derived from a passphrase, as `Pointer::encrypt_with_passphrase` does.
+    pub fn with_passphrase(
+        passphrase: &str,
+        params: KdfParams,
+        chunker: Chunker,
+        backend: &'a mut dyn Backend,
+    ) -> Result<PointerBuilder<'a>, Error> {
+        let kek = params.derive_key(passphrase.as_bytes())?;
+
+        let mut builder = PointerBuilder::with_kek(&kek, chunker, backend)?;
+        builder.kdf = Some(params);
+
+        Ok(builder)
+    }
+
+    /// Creates a builder which encrypts with a random data key wrapped to each
+    /// of the `recipients`, as `Pointer::encrypt_for_recipients` does.
+    pub fn for_recipients(
+        recipients: &[Recipient],
+        chunker: Chunker,
+        backend: &'a mut dyn Backend,
+    ) -> Result<PointerBuilder<'a>, Error> {
+        let key = random_key();
+        let stanzas = wrap_for_recipients(&key, recipients)?;
+
+        let mut builder = PointerBuilder::new(&key, chunker, backend)?;
+        builder.recipients = stanzas;
+
+        Ok(builder)
+    }
+
+    /// Encrypts the blocks in convergent mode, as
+    /// `Pointer::encrypt_convergent` does.
+    pub fn convergent(mut self) -> Self {
+        self.convergent = true;
+        self
+    }
+
+    /// Sets the cipher the pointer is encrypted with, as `Pointer::with_cipher`
+    /// does.
+    pub fn cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Makes the pointer searchable, as `Pointer::searchable` does.
+    pub fn searchable(mut self, tokens_key: &Key) -> Self {
+        self.tokens_key = Some(tokens_key.clone());
+        self
+    }
+
+    /// Attaches `metadata` to the pointer, as `Pointer::with_metadata` does.
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.manifest.metadata = Some(metadata);
+        self
+    }
+
+    /// Cuts the next block and stores the previous one.
+    fn emit_block(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut block = Block::for_chunker(&self.chunker);
+        block.write_all(data)?;
+
+        if self.manifest.head_block.is_none() {
+            self.manifest.head_block = Some(block.cid.to_string());
+        }
+        self.manifest.block_cids.push(block.cid.to_string());
+        self.manifest.lengths.push(data.len() as u64);
+
+        if let Some(previous) = self.pending.replace(block) {
+            self.seal_block(previous, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Encrypts and stores the next block.
+    fn seal_block(&mut self, block: Block, last: bool) -> Result<(), Error> {
+        let enc_block = if self.convergent {
+            let (enc_block, block_key) = block.encrypt_convergent_with(self.cipher)?;
+            self.manifest.block_keys.push(block_key);
+            enc_block
+        } else {
+            let ad = block_ad(&self.stream_id, self.links.len(), last);
+            block.encrypt_with(self.cipher, &self.blocks_key, &ad)?
+        };
+        let link = store_block(&enc_block, self.backend)?;
+        self.links.push(link);
+
+        Ok(())
+    }
+
+    /// Stores the last block and the pointer, returning the root CID of the
+    /// stored pointer.
+    pub fn finish(mut self) -> Result<String, Error> {
+        let data = std::mem::take(&mut self.buf);
+        for chunk in self.chunker.split(&data) {
+            self.emit_block(chunk)?;
+        }
+        if let Some(last) = self.pending.take() {
+            self.seal_block(last, true)?;
+        }
+
+        self.manifest.cid = root_cid(&self.manifest.block_cids)?;
+        let size = self.manifest.lengths.iter().sum();
+        if let Some(metadata) = self.manifest.metadata.as_mut() {
+            metadata.size = size;
+        }
+
+        let mut pointer = Pointer::encrypted(self.chunker, self.cipher, self.stream_id);
+        pointer.0.links = self.links;
+        pointer.0.kdf = self.kdf;
+        pointer.0.wrapped_key = self.wrapped_key;
+        pointer.0.recipients = self.recipients;
+        if let Some(tokens_key) = &self.tokens_key {
+            pointer.0.tag_tokens = search_tokens(self.manifest.metadata.as_ref(), tokens_key)?;
+        }
+        pointer.seal_manifest(&self.manifest, &self.key)?;
+
+        pointer.store(self.backend)
+    }
+}
+
+impl Write for PointerBuilder<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        let n = std::cmp::min(buf.len(), self.chunker.max_size() - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+
+        // emits all the blocks which can be cut from the buffered data
+        let mut data = std::mem::take(&mut self.buf);
+        let mut start = 0;
+        while let Some(len) = self.chunker.cut(&data[start..]) {
+            self.emit_block(&data[start..start + len])?;
+            start += len;
+        }
+        data.drain(..start);
+        self.buf = data;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::crypto::{tag_token, Identity};
+    use crate::structs::{Match, Tags};
+    use std::io::Read;
+
+    #[test]
+    fn build_and_read() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let file_buffer = std::fs::read(file!()).unwrap();
+
+        let mut backend = MemoryBackend::new();
+        let chunker = Chunker::Fixed { size: 1000 };
+        let mut builder = PointerBuilder::new(key, chunker, &mut backend).unwrap();
+
+        // writes in chunks which do not align with the block size
+        for chunk in file_buffer.chunks(333) {
+            builder.write_all(chunk).unwrap();
+            assert!(builder.buf.len() < 1000);
+        }
+        let root = builder.finish().unwrap();
+
+        let expected_blocks = file_buffer.len().div_ceil(1000);
+        assert_eq!(backend.len(), expected_blocks + 1);
+
+        // same plaintext CID as a pointer created in memory
+        let in_memory = Pointer::from_with_block_size(&file_buffer, 1000).unwrap();
+        let mut pointer = Pointer::load(&root, &backend).unwrap();
+        assert_eq!(pointer.blocks_len(), expected_blocks);
+
+        pointer = pointer.decrypt(key).unwrap();
+        assert_eq!(pointer.cid(), in_memory.cid());
+        let mut out = vec![];
+        pointer.read_to_end(&mut out).unwrap();
+        assert_eq!(out, file_buffer);
+    }
+
+    #[test]
+    fn build_with_passphrase() {
+        let params = KdfParams::new(64, 1, 1);
+        let data = [7_u8; 3000];
+
+        let mut backend = MemoryBackend::new();
+        let mut builder =
+            PointerBuilder::with_passphrase("passphrase", params, Chunker::default(), &mut backend)
+                .unwrap();
+        std::io::copy(&mut &data[..], &mut builder).unwrap();
+        let root = builder.finish().unwrap();
+
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        let key = header.derive_key("passphrase").unwrap();
+        let mut reader = header.reader(&key, &backend).unwrap();
+        assert!(reader.pointer().metadata().is_none());
+
+        let mut out = vec![];
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data.to_vec());
+    }
+
+    #[test]
+    fn build_with_cipher() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data = [7_u8; 3000];
+
+        for cipher in Cipher::ALL {
+            for convergent in [false, true] {
+                let mut backend = MemoryBackend::new();
+                let mut builder =
+                    PointerBuilder::new(key, Chunker::Fixed { size: 1000 }, &mut backend)
+                        .unwrap()
+                        .cipher(cipher);
+                if convergent {
+                    builder = builder.convergent();
+                }
+                builder.write_all(&data).unwrap();
+                let root = builder.finish().unwrap();
+
+                let header = Pointer::load_header(&root, &backend).unwrap();
+                assert_eq!(header.cipher(), cipher);
+                let mut out = vec![];
+                header
+                    .reader(key, &backend)
+                    .unwrap()
+                    .read_to_end(&mut out)
+                    .unwrap();
+                assert_eq!(out, data.to_vec());
+            }
+        }
+    }
+
+    #[test]
+    fn build_for_recipients() {
+        let identity = Identity::generate();
+        let data = [7_u8; 3000];
+
+        let mut backend = MemoryBackend::new();
+        let mut builder = PointerBuilder::for_recipients(
+            &[identity.recipient()],
+            Chunker::default(),
+            &mut backend,
+        )
+        .unwrap();
+        std::io::copy(&mut &data[..], &mut builder).unwrap();
+        let root = builder.finish().unwrap();
+
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        let key = header.unwrap_key(&identity).unwrap();
+        let mut out = vec![];
+        header
+            .reader(&key, &backend)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data.to_vec());
+
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        assert!(header.unwrap_key(&Identity::generate()).is_err());
+    }
+
+    #[test]
+    fn build_with_metadata() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data = [7_u8; 3000];
+        let metadata = Metadata::for_file(
+            std::path::Path::new("/tmp/report.pdf"),
+            Tags { values: vec![] },
+        )
+        .unwrap();
+
+        let mut backend = MemoryBackend::new();
+        let mut builder = PointerBuilder::new(key, Chunker::default(), &mut backend)
+            .unwrap()
+            .metadata(metadata.clone());
+        builder.write_all(&data).unwrap();
+        let root = builder.finish().unwrap();
+
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        assert!(!header.query(tag_token(key, "report")).unwrap());
+        let reader = header.reader(key, &backend).unwrap();
+        let stored = reader.pointer().metadata().unwrap();
+        assert_eq!(stored.filename, Some("report.pdf".to_string()));
+        assert_eq!(stored.mime_type, Some("application/pdf".to_string()));
+        assert_eq!(stored.created, metadata.created);
+        assert_eq!(stored.size, data.len() as u64);
+    }
+
+    #[test]
+    fn build_searchable() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let search_key = &Key::from(b"a search key, not the data key..");
+        let tags = Tags {
+            values: vec!["report".to_string()],
+        };
+
+        let mut backend = MemoryBackend::new();
+        let mut builder = PointerBuilder::new(key, Chunker::default(), &mut backend)
+            .unwrap()
+            .metadata(Metadata::new(tags).unwrap())
+            .searchable(search_key);
+        builder.write_all(&[7_u8; 3000]).unwrap();
+        let root = builder.finish().unwrap();
+
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        assert!(header.query(tag_token(search_key, "report")).unwrap());
+        assert!(!header.query(tag_token(search_key, "invoice")).unwrap());
+    }
+
+    #[test]
+    fn build_content_defined() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let file_buffer = std::fs::read(file!()).unwrap();
+        let chunker = Chunker::fast_cdc(256);
+
+        let mut backend = MemoryBackend::new();
+        let mut builder = PointerBuilder::new(key, chunker, &mut backend).unwrap();
+        for chunk in file_buffer.chunks(100) {
+            builder.write_all(chunk).unwrap();
+        }
+        let root = builder.finish().unwrap();
+
+        // same blocks as the pointer created in memory
+        let in_memory = Pointer::from_with_chunker(&file_buffer, chunker).unwrap();
+        let pointer = Pointer::load(&root, &backend).unwrap();
+        assert_eq!(pointer.chunker(), &chunker);
+
+        let mut pointer = pointer.decrypt(key).unwrap();
+        assert_eq!(pointer.cid(), in_memory.cid());
+        let mut out = vec![];
+        pointer.read_to_end(&mut out).unwrap();
+        assert_eq!(out, file_buffer);
+    }
+
+    #[test]
+    fn build_convergent() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let other_key = &Key::from(b"another very very secret key....");
+        let file_buffer = std::fs::read(file!()).unwrap();
+        let chunker = Chunker::Fixed { size: 1000 };
+
+        let mut backend = MemoryBackend::new();
+        let mut roots = vec![];
+        for k in [key, other_key] {
+            let mut builder = PointerBuilder::new(k, chunker, &mut backend)
+                .unwrap()
+                .convergent();
+            builder.write_all(&file_buffer).unwrap();
+            roots.push(builder.finish().unwrap());
+        }
+
+        // the blocks of both pointers are stored once
+        let expected_blocks = file_buffer.len().div_ceil(1000);
+        assert_ne!(roots[0], roots[1]);
+        assert_eq!(backend.len(), expected_blocks + 2);
+
+        // same blocks as the pointer encrypted in memory
+        let mut in_memory = Pointer::from_with_chunker(&file_buffer, chunker)
+            .unwrap()
+            .encrypt_convergent(key)
+            .unwrap();
+        in_memory.store(&mut MemoryBackend::new()).unwrap();
+        let pointer = Pointer::load_header(&roots[0], &backend).unwrap();
+        assert_eq!(pointer.0.links, in_memory.0.links);
+
+        let header = Pointer::load_header(&roots[1], &backend).unwrap();
+        let mut out = vec![];
+        header
+            .reader(other_key, &backend)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, file_buffer);
+    }
+
+    #[test]
+    fn invalid_chunker() {
+        let mut backend = MemoryBackend::new();
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let chunker = Chunker::Fixed { size: 0 };
+        assert!(PointerBuilder::new(key, chunker, &mut backend).is_err());
+    }
+}
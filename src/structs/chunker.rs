@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+
+use super::{DEFAULT_BLOCK_SIZE, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+use crate::error::Error;
+
+/// Chunker defines how data is split into blocks. It is recorded in the pointer,
+/// so that readers know how a pointer was built.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Chunker {
+    /// Blocks of `size` bytes, except for the last one
+    Fixed { size: usize },
+    /// Content-defined chunking with the FastCDC algorithm. Block boundaries
+    /// are picked based on the content, so that an insertion or deletion only
+    /// changes the blocks around the edit.
+    FastCdc { min: usize, avg: usize, max: usize },
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Chunker::Fixed {
+            size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+}
+
+impl Chunker {
+    /// Returns a FastCDC chunker with the given average block size, with the
+    /// minimum and maximum sizes at a quarter and four times the average.
+    pub fn fast_cdc(avg: usize) -> Self {
+        Chunker::FastCdc {
+            min: avg / 4,
+            avg,
+            max: avg * 4,
+        }
+    }
+
+    /// Checks that the chunker parameters are valid.
+    pub fn validate(&self) -> Result<(), Error> {
+        match *self {
+            Chunker::Fixed { size } => {
+                if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&size) {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid block size {}. Must be between {} and {} bytes",
+                        size, MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+                    )));
+                }
+            }
+            // the gear hash only depends on the last 64 bytes, so with a smaller
+            // minimum a cut point would depend on the start of the block
+            Chunker::FastCdc { min, avg, max } => {
+                if min < MIN_BLOCK_SIZE || min > avg || avg > max || max > MAX_BLOCK_SIZE {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid FastCDC sizes min={} avg={} max={}. Must be {} <= min <= avg <= max <= {}",
+                        min, avg, max, MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Maximum size of a block
+    pub fn max_size(&self) -> usize {
+        match *self {
+            Chunker::Fixed { size } => size,
+            Chunker::FastCdc { max, .. } => max,
+        }
+    }
+
+    /// Returns the size of the block at the start of `data`, or `None` if more
+    /// data is needed to find where the block ends. When there is no more data,
+    /// whatever is left after the last cut point is the last block.
+    pub fn cut(&self, data: &[u8]) -> Option<usize> {
+        match *self {
+            Chunker::Fixed { size } => {
+                if data.len() >= size {
+                    Some(size)
+                } else {
+                    None
+                }
+            }
+            Chunker::FastCdc { min, avg, max } => fast_cdc_cut(data, min, avg, max),
+        }
+    }
+
+    /// Splits `data` in blocks.
+    pub fn split<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut chunks = vec![];
+        let mut rest = data;
+
+        while let Some(n) = self.cut(rest) {
+            chunks.push(&rest[..n]);
+            rest = &rest[n..];
+        }
+        if !rest.is_empty() {
+            chunks.push(rest);
+        }
+
+        chunks
+    }
+}
+
+/// FastCDC cut point search with normalized chunking: a harder to match mask
+/// is used before the average size and an easier one after it, so that block
+/// sizes concentrate around the average.
+fn fast_cdc_cut(data: &[u8], min: usize, avg: usize, max: usize) -> Option<usize> {
+    if data.len() <= min {
+        return None;
+    }
+
+    let bits = usize::BITS - 1 - avg.leading_zeros();
+    let mask_s = mask(bits + 2);
+    let mask_l = mask(bits - 2);
+
+    let end = std::cmp::min(data.len(), max);
+    let normal = std::cmp::min(avg, end);
+
+    let mut h: u64 = 0;
+    for (i, b) in data.iter().enumerate().take(end).skip(min) {
+        h = (h << 1).wrapping_add(GEAR[*b as usize]);
+        let mask = if i < normal { mask_s } else { mask_l };
+        if h & mask == 0 {
+            return Some(i + 1);
+        }
+    }
+
+    if data.len() >= max {
+        Some(max)
+    } else {
+        None
+    }
+}
+
+/// Mask over the `bits` most significant bits, which depend on the most bytes
+/// of the rolling window.
+fn mask(bits: u32) -> u64 {
+    if bits == 0 {
+        return 0;
+    }
+    u64::MAX << (64 - bits)
+}
+
+/// Random values used by the gear rolling hash, generated at compile time
+/// with splitmix64 from a fixed seed. Changing them changes all cut points.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x656e_2d63_7279_7074; // "en-crypt"
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random test data
+    fn random_data(len: usize) -> Vec<u8> {
+        let mut state: u64 = 42;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fixed_split() {
+        let data = [1_u8; 1000];
+        let chunks = Chunker::Fixed { size: 300 }.split(&data);
+        assert_eq!(Chunker::Fixed { size: 300 }.max_size(), 300);
+        let sizes: Vec<usize> = chunks.iter().map(|c| c.len()).collect();
+        assert_eq!(sizes, vec![300, 300, 300, 100]);
+    }
+
+    #[test]
+    fn fast_cdc_split() {
+        let data = random_data(100_000);
+        let chunker = Chunker::fast_cdc(512);
+        assert!(chunker.validate().is_ok());
+
+        let chunks = chunker.split(&data);
+        assert_eq!(chunks.concat(), data);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() > 128 && chunk.len() <= 2048);
+        }
+
+        // block sizes are around the average
+        let avg = data.len() / chunks.len();
+        assert!(avg > 256 && avg < 1024, "average block size {}", avg);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(Chunker::default().validate().is_ok());
+        assert!(Chunker::Fixed { size: 0 }.validate().is_err());
+        assert!(Chunker::Fixed {
+            size: MIN_BLOCK_SIZE - 1
+        }
+        .validate()
+        .is_err());
+        assert!(Chunker::Fixed {
+            size: MAX_BLOCK_SIZE + 1
+        }
+        .validate()
+        .is_err());
+        assert!(Chunker::FastCdc {
+            min: 32,
+            avg: 512,
+            max: 2048
+        }
+        .validate()
+        .is_err());
+        assert!(Chunker::FastCdc {
+            min: 1024,
+            avg: 512,
+            max: 2048
+        }
+        .validate()
+        .is_err());
+        assert!(Chunker::fast_cdc(MAX_BLOCK_SIZE).validate().is_err());
+    }
+}
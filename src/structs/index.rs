@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+use super::{Match, Pointer, Term};
+use crate::backend::Backend;
+use crate::crypto::{index_key, index_ref, Key};
+use crate::error::Error;
+
+/// TagIndex maps the tag tokens of searchable pointers to the CIDs of the
+/// pointers which have them, so that a search does not need to load every
+/// pointer in the backend. The index is stored as an en-crypt pointer,
+/// encrypted with a key derived from the search key, and found through a
+/// backend reference named after the search key.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TagIndex {
+    tokens: BTreeMap<String, BTreeSet<String>>,
+    /// CIDs of all the indexed pointers, including those without tags
+    #[serde(default)]
+    members: BTreeSet<String>,
+}
+
+/// A pointer in the index, which matches the tag tokens it is indexed under.
+struct IndexedPointer<'a> {
+    index: &'a TagIndex,
+    cid: &'a str,
+}
+
+impl Match for IndexedPointer<'_> {
+    fn match_term(&self, term: &Term) -> Result<bool, Error> {
+        Ok(self
+            .index
+            .tokens
+            .get(term.token()?)
+            .is_some_and(|cids| cids.contains(self.cid)))
+    }
+}
+
+impl TagIndex {
+    /// Loads the index of `search_key`, or returns `None` if there is none in
+    /// the backend yet.
+    pub fn load(backend: &dyn Backend, search_key: &Key) -> Result<Option<TagIndex>, Error> {
+        let root = match backend.get_ref(&index_ref(search_key))? {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+
+        let mut raw = vec![];
+        Pointer::load_header(&root, backend)?
+            .reader(&index_key(search_key), backend)?
+            .read_to_end(&mut raw)?;
+        let index = serde_cbor::from_slice(&raw)
+            .map_err(|e| Error::Decode(format!("Error decoding tag index: {}", e)))?;
+
+        Ok(Some(index))
+    }
+
+    /// Stores the index and points the reference of `search_key` to it. The
+    /// previous version of the index is deleted from the backend.
+    pub fn save(&self, backend: &mut dyn Backend, search_key: &Key) -> Result<String, Error> {
+        let key = index_key(search_key);
+        let name = index_ref(search_key);
+
+        let raw = serde_cbor::to_vec(self).map_err(|e| Error::Encode(e.to_string()))?;
+        let root = Pointer::from(&raw)?.encrypt(&key)?.store(backend)?;
+
+        let previous = backend.get_ref(&name)?;
+        backend.set_ref(&name, Some(&root))?;
+        if let Some(previous) = previous {
+            if previous != root {
+                Pointer::delete(&previous, &key, backend)?;
+            }
+        }
+
+        Ok(root)
+    }
+
+    /// Indexes the pointer `cid` under its tag tokens. A pointer without tags
+    /// is still indexed, so that it matches negated queries.
+    pub fn insert(&mut self, cid: &str, tokens: &[String]) {
+        self.members.insert(cid.to_string());
+        for token in tokens {
+            self.tokens
+                .entry(token.clone())
+                .or_default()
+                .insert(cid.to_string());
+        }
+    }
+
+    /// Removes the pointer `cid` from the index. Returns whether it was
+    /// indexed.
+    pub fn remove(&mut self, cid: &str) -> bool {
+        self.tokens.retain(|_, cids| {
+            cids.remove(cid);
+            !cids.is_empty()
+        });
+        self.members.remove(cid)
+    }
+
+    /// Indexes the pointer `cid` as `new_cid`, under the same tag tokens, such
+    /// as once it is stored again with a new header. Returns whether it was
+    /// indexed.
+    pub fn rename(&mut self, cid: &str, new_cid: &str) -> bool {
+        if !self.members.remove(cid) {
+            return false;
+        }
+        self.members.insert(new_cid.to_string());
+        for cids in self.tokens.values_mut() {
+            if cids.remove(cid) {
+                cids.insert(new_cid.to_string());
+            }
+        }
+        true
+    }
+
+    /// CIDs of all the indexed pointers
+    pub fn pointers(&self) -> BTreeSet<&str> {
+        self.members.iter().map(|c| c.as_str()).collect()
+    }
+
+    /// Returns the CIDs of the indexed pointers for which `predicate` is true.
+    /// The predicate is given a `Match` which matches the tag tokens of each
+    /// pointer.
+    pub fn matching<F>(&self, mut predicate: F) -> Result<Vec<String>, Error>
+    where
+        F: FnMut(&dyn Match) -> Result<bool, Error>,
+    {
+        let mut cids = vec![];
+        for cid in self.pointers() {
+            if predicate(&IndexedPointer { index: self, cid })? {
+                cids.push(cid.to_string());
+            }
+        }
+        Ok(cids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::crypto::tag_tokens_key;
+
+    fn tokens(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn insert_remove_match() {
+        let mut index = TagIndex::default();
+        index.insert("cid1", &tokens(&["invoice", "2026"]));
+        index.insert("cid2", &tokens(&["receipt", "2026"]));
+        assert_eq!(index.pointers().len(), 2);
+
+        let matching = index
+            .matching(|m| Ok(m.query("2026".to_string())? && !m.query("receipt".to_string())?))
+            .unwrap();
+        assert_eq!(matching, vec!["cid1"]);
+
+        assert!(index.remove("cid1"));
+        assert!(!index.remove("cid1"));
+        assert_eq!(
+            index.pointers().into_iter().collect::<Vec<_>>(),
+            vec!["cid2"]
+        );
+        assert!(!index.tokens.contains_key("invoice"));
+
+        // pointers without tags are indexed, and match negated queries
+        index.insert("cid3", &[]);
+        let matching = index
+            .matching(|m| Ok(!m.query("receipt".to_string())?))
+            .unwrap();
+        assert_eq!(matching, vec!["cid3"]);
+
+        assert!(index.rename("cid2", "cid4"));
+        assert!(!index.rename("cid2", "cid5"));
+        assert_eq!(
+            index.pointers().into_iter().collect::<Vec<_>>(),
+            vec!["cid3", "cid4"]
+        );
+        let matching = index.matching(|m| m.query("receipt".to_string())).unwrap();
+        assert_eq!(matching, vec!["cid4"]);
+    }
+
+    #[test]
+    fn load_save() {
+        let search_key = &Key::from(b"a search key, not the data key..");
+        let mut backend = MemoryBackend::new();
+        assert_eq!(TagIndex::load(&backend, search_key).unwrap(), None);
+
+        let mut index = TagIndex::default();
+        index.insert("cid1", &tokens(&["invoice"]));
+        index.save(&mut backend, search_key).unwrap();
+        let objects = backend.len();
+
+        let mut loaded = TagIndex::load(&backend, search_key).unwrap().unwrap();
+        assert_eq!(loaded, index);
+
+        // the previous version is deleted once the index is updated
+        loaded.insert("cid2", &tokens(&["receipt"]));
+        loaded.save(&mut backend, search_key).unwrap();
+        assert_eq!(backend.len(), objects);
+        assert_eq!(
+            TagIndex::load(&backend, search_key).unwrap().unwrap(),
+            loaded
+        );
+
+        // the index is encrypted, and other search keys have their own index
+        for cid in backend.list().unwrap() {
+            let raw = backend.get(&cid).unwrap().unwrap();
+            assert!(!raw.windows(7).any(|w| w == b"invoice"));
+        }
+        let other_key = &Key::from(b"another very very secret key....");
+        assert_eq!(TagIndex::load(&backend, other_key).unwrap(), None);
+
+        // the tag tokens key, a search-only capability, does not open it
+        let tokens_key = &tag_tokens_key(search_key);
+        assert_eq!(TagIndex::load(&backend, tokens_key).unwrap(), None);
+        let root = backend.get_ref(&index_ref(search_key)).unwrap().unwrap();
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        assert!(matches!(
+            header.reader(tokens_key, &backend),
+            Err(Error::WrongKey)
+        ));
+    }
+}
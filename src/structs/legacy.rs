@@ -0,0 +1,198 @@
+use serde::Deserialize;
+use zeroize::Zeroizing;
+
+use super::{cid_of, root_cid, Pointer, LEGACY_BLOCK_SIZE};
+use crate::crypto::{Cipher, Key, KEY_SIZE_BYTES};
+use crate::error::Error;
+
+const LEGACY_NONCE_SIZE: usize = 12;
+
+/// Pointer as written by the first version of en-crypt: a single CBOR object
+/// which holds its blocks, split in blocks of `LEGACY_BLOCK_SIZE` bytes. The
+/// placeholder metadata and the blocks are encrypted with AES-256-GCM under
+/// the passphrase itself, padded with zeros to 32 bytes, and each nonce is
+/// the start of the plaintext CID. The CIDs of the encrypted pointer and
+/// blocks are the plaintext CIDs followed by "/encrypted".
+#[derive(Deserialize)]
+struct LegacyPointer {
+    cid: String,
+    metadata: Vec<u8>,
+    blocks: Vec<LegacyBlock>,
+}
+
+#[derive(Deserialize)]
+struct LegacyBlock {
+    cid: String,
+    data: Vec<u8>,
+}
+
+/// Returns the plaintext CID of a legacy pointer or block.
+fn plaintext_cid(cid: &str) -> &str {
+    cid.split('/').next().unwrap_or_default()
+}
+
+/// Returns the nonce a legacy pointer or block was encrypted with.
+fn legacy_nonce(cid: &str) -> Result<&[u8], Error> {
+    plaintext_cid(cid)
+        .as_bytes()
+        .get(..LEGACY_NONCE_SIZE)
+        .ok_or_else(|| Error::Decode(format!("Invalid legacy CID: {}", cid)))
+}
+
+/// Returns the key legacy pointers were encrypted with, the passphrase padded
+/// with zeros.
+fn legacy_key(passphrase: &str) -> Result<Key, Error> {
+    let passphrase = passphrase.as_bytes();
+    if passphrase.len() > KEY_SIZE_BYTES {
+        return Err(Error::InvalidArgument(format!(
+            "Legacy passphrases are at most {} bytes",
+            KEY_SIZE_BYTES
+        )));
+    }
+    let mut bytes = Zeroizing::new([0; KEY_SIZE_BYTES]);
+    bytes[..passphrase.len()].copy_from_slice(passphrase);
+    Ok(Key::from(&*bytes))
+}
+
+impl Pointer {
+    /// Decrypts a pointer written by the first version of en-crypt, given its
+    /// raw CBOR encoding, and returns the data. Such pointers hold their
+    /// blocks and are encrypted with the passphrase itself, so they can only
+    /// be read, to be stored again in the current format. Every block is
+    /// checked against its CID, and the blocks against the pointer CID.
+    pub fn decrypt_legacy(raw: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+        let pointer: LegacyPointer = serde_cbor::from_slice(raw)
+            .map_err(|e| Error::Decode(format!("Error decoding legacy pointer: {}", e)))?;
+        let key = legacy_key(passphrase)?;
+        let cipher = Cipher::Aes256Gcm;
+
+        cipher
+            .open(&key, legacy_nonce(&pointer.cid)?, &pointer.metadata, &[])
+            .ok_or(Error::WrongKey)?;
+
+        let mut data = vec![];
+        let mut block_cids = vec![];
+        for block in &pointer.blocks {
+            let plaintext = cipher
+                .open(&key, legacy_nonce(&block.cid)?, &block.data, &[])
+                .ok_or_else(|| {
+                    Error::Authentication(format!("Block {} failed authentication", block.cid))
+                })?;
+            if plaintext.len() > LEGACY_BLOCK_SIZE {
+                return Err(Error::CorruptBlock(format!(
+                    "Block {} is larger than {} bytes",
+                    block.cid, LEGACY_BLOCK_SIZE
+                )));
+            }
+
+            let cid = cid_of(&plaintext)?;
+            if cid != plaintext_cid(&block.cid) {
+                return Err(Error::IntegrityMismatch(block.cid.clone()));
+            }
+            block_cids.push(cid);
+            data.extend_from_slice(&plaintext);
+        }
+
+        if root_cid(&block_cids)? != plaintext_cid(&pointer.cid) {
+            return Err(Error::IntegrityMismatch(pointer.cid));
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_cbor::Value;
+
+    /// Pointer stored by the first version of en-crypt, with
+    /// `en-crypt store -f data -k "legacy passphrase"` where `data` holds the
+    /// bytes `i % 251` for `i` in `0..4103`, so in three blocks. That version
+    /// printed the pointer as a list of bytes, kept here as raw CBOR.
+    const LEGACY_POINTER: &[u8] = include_bytes!("../../tests/fixtures/legacy_pointer.cbor");
+    const LEGACY_PASSPHRASE: &str = "legacy passphrase";
+
+    fn legacy_data() -> Vec<u8> {
+        (0..LEGACY_BLOCK_SIZE * 2 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    /// Applies `f` to the CBOR value of the legacy pointer fixture.
+    fn tampered<F: FnOnce(&mut Vec<Value>)>(f: F) -> Vec<u8> {
+        let mut value: Value = serde_cbor::from_slice(LEGACY_POINTER).unwrap();
+        if let Value::Map(fields) = &mut value {
+            if let Some(Value::Array(blocks)) = fields.get_mut(&Value::Text("blocks".to_string())) {
+                f(blocks);
+            }
+        }
+        serde_cbor::to_vec(&value).unwrap()
+    }
+
+    #[test]
+    fn decrypt_legacy() {
+        let data = Pointer::decrypt_legacy(LEGACY_POINTER, LEGACY_PASSPHRASE).unwrap();
+        assert_eq!(data, legacy_data());
+
+        // the data can be stored again in the current format
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let mut decrypted = Pointer::from_with_block_size(&data, LEGACY_BLOCK_SIZE)
+            .unwrap()
+            .encrypt(key)
+            .unwrap()
+            .decrypt(key)
+            .unwrap();
+        let mut out = vec![];
+        std::io::Read::read_to_end(&mut decrypted, &mut out).unwrap();
+        assert_eq!(out, data);
+
+        assert!(matches!(
+            Pointer::decrypt_legacy(LEGACY_POINTER, "another passphrase"),
+            Err(Error::WrongKey)
+        ));
+        assert!(matches!(
+            Pointer::decrypt_legacy(LEGACY_POINTER, &"a".repeat(KEY_SIZE_BYTES + 1)),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        // pointers in the current format are not legacy pointers
+        let raw = serde_cbor::to_vec(&Pointer::from(&data).unwrap()).unwrap();
+        assert!(matches!(
+            Pointer::decrypt_legacy(&raw, LEGACY_PASSPHRASE),
+            Err(Error::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn tampered_legacy_pointer() {
+        // a modified block fails authentication
+        let raw = tampered(|blocks| {
+            if let Value::Map(block) = &mut blocks[1] {
+                if let Some(Value::Array(data)) = block.get_mut(&Value::Text("data".to_string())) {
+                    if let Value::Integer(byte) = data[0] {
+                        data[0] = Value::Integer(byte ^ 1);
+                    }
+                }
+            }
+        });
+        assert!(matches!(
+            Pointer::decrypt_legacy(&raw, LEGACY_PASSPHRASE),
+            Err(Error::Authentication(_))
+        ));
+
+        // dropped or reordered blocks do not match the pointer CID
+        let raw = tampered(|blocks| {
+            blocks.pop();
+        });
+        assert!(matches!(
+            Pointer::decrypt_legacy(&raw, LEGACY_PASSPHRASE),
+            Err(Error::IntegrityMismatch(_))
+        ));
+        let raw = tampered(|blocks| blocks.swap(0, 1));
+        assert!(matches!(
+            Pointer::decrypt_legacy(&raw, LEGACY_PASSPHRASE),
+            Err(Error::IntegrityMismatch(_))
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+
+use super::metadata::optional_metadata;
+use super::{block_ad, root_cid, Block, Metadata};
+use crate::crypto::{Cipher, Key};
+use crate::error::Error;
+
+/// Manifest holds everything about the plaintext of an encrypted pointer: its
+/// CID, the CID and size of each block and the pointer metadata. It is stored
+/// encrypted in the pointer header, so that a stored pointer does not reveal
+/// the hashes of its content.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(super) struct Manifest {
+    /// CID of the plaintext pointer
+    pub cid: String,
+    pub head_block: Option<String>,
+    /// Plaintext CID of each block, in order
+    pub block_cids: Vec<String>,
+    /// Size in bytes of the plaintext of each block, in order
+    pub lengths: Vec<u64>,
+    /// Key of each block of a pointer encrypted in convergent mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub block_keys: Vec<Key>,
+    #[serde(default, deserialize_with = "optional_metadata")]
+    pub metadata: Option<Metadata>,
+}
+
+impl Manifest {
+    /// Encrypts the manifest of a pointer with `blocks` blocks. The manifest
+    /// is bound to the pointer stream id and number of blocks, so that blocks
+    /// cannot be dropped from the pointer header. Returns the ciphertext and
+    /// the nonce.
+    pub fn encrypt(
+        &self,
+        cipher: Cipher,
+        key: &Key,
+        stream_id: &[u8],
+        blocks: usize,
+    ) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let raw = serde_cbor::to_vec(self).map_err(|e| Error::Encode(e.to_string()))?;
+
+        let nonce = cipher.random_nonce();
+        let ctext = cipher
+            .seal(key, &nonce, &raw, &manifest_ad(stream_id, blocks))
+            .ok_or_else(|| Error::Encode("Error encrypting pointer manifest".to_string()))?;
+
+        Ok((ctext, nonce))
+    }
+
+    /// Decrypts the manifest of a pointer with `blocks` blocks and checks that
+    /// it is consistent. Fails with `Error::Authentication` if it cannot be
+    /// decrypted with `key`: the key commitment of the pointer is checked
+    /// first, so the pointer header was then tampered with, for instance by
+    /// dropping blocks.
+    pub fn decrypt(
+        cipher: Cipher,
+        ctext: &[u8],
+        nonce: &[u8],
+        key: &Key,
+        stream_id: &[u8],
+        blocks: usize,
+    ) -> Result<Manifest, Error> {
+        if nonce.len() != cipher.nonce_size() {
+            return Err(Error::Decode(
+                "Pointer has an invalid manifest nonce".to_string(),
+            ));
+        }
+
+        let raw = cipher
+            .open(key, nonce, ctext, &manifest_ad(stream_id, blocks))
+            .ok_or_else(|| Error::Authentication("pointer manifest".to_string()))?;
+
+        let manifest: Manifest = serde_cbor::from_slice(&raw)
+            .map_err(|e| Error::Decode(format!("Error decoding pointer manifest: {}", e)))?;
+
+        if manifest.block_cids.len() != blocks || manifest.lengths.len() != blocks {
+            return Err(Error::Decode(
+                "Pointer manifest does not match the pointer blocks".to_string(),
+            ));
+        }
+        if !manifest.block_keys.is_empty() && manifest.block_keys.len() != blocks {
+            return Err(Error::Decode(
+                "Pointer manifest does not have a key for every block".to_string(),
+            ));
+        }
+        if root_cid(&manifest.block_cids)? != manifest.cid {
+            return Err(Error::IntegrityMismatch(manifest.cid));
+        }
+
+        Ok(manifest)
+    }
+
+    /// Whether the blocks are encrypted in convergent mode
+    pub fn is_convergent(&self) -> bool {
+        !self.block_keys.is_empty()
+    }
+
+    /// Decrypts the block at `idx` of the pointer and checks it against the
+    /// block CID in the manifest.
+    pub fn open_block(
+        &self,
+        cipher: Cipher,
+        block: Block,
+        key: &Key,
+        stream_id: &[u8],
+        idx: usize,
+    ) -> Result<Block, Error> {
+        let block = if self.is_convergent() {
+            block.decrypt_with(cipher, &self.block_keys[idx], &[])?
+        } else {
+            let last = idx + 1 == self.block_cids.len();
+            block.decrypt_with(cipher, key, &block_ad(stream_id, idx, last))?
+        };
+
+        if block.cid != self.block_cids[idx] {
+            return Err(Error::IntegrityMismatch(self.block_cids[idx].clone()));
+        }
+
+        Ok(block)
+    }
+}
+
+/// Associated data of the manifest.
+fn manifest_ad(stream_id: &[u8], blocks: usize) -> Vec<u8> {
+    let mut ad = stream_id.to_vec();
+    ad.extend_from_slice(&(blocks as u64).to_be_bytes());
+    ad
+}
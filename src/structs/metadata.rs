@@ -1,88 +1,274 @@
-use aes_gcm::aead::{generic_array::GenericArray, Aead, NewAead};
-use aes_gcm::Aes256Gcm;
 use cid::{Cid, Version};
 use multihash::{Code, MultihashDigest};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-const NONCE_SIZE_BYTES: usize = 12;
-const SHA256_CODE: u64 = 0x12;
+use super::cid_of;
+use super::query::{is_attribute, Cmp, Query, Term};
+use super::value::{decode_fields, encode_fields, Fields, Value, TAGS_FIELD};
+use crate::crypto::{metadata_key, tag_token, Cipher, Key};
+use crate::error::Error;
+
+/// Multicodec code of DAG-CBOR
+const DAG_CBOR_CODE: u64 = 0x71;
 
 /// Trait Match allows a query to run against object that implements it. The
 /// result of the query is a boolean, representing whether the query has matched
-/// the object or not. The query is encoded as a vector of bytes.
+/// the object or not. Implementations evaluate the single terms of a query,
+/// which is parsed with `Query::parse`.
 pub trait Match {
-    fn query(&self, query: String) -> Result<bool, String>;
+    /// Whether a single term of a query matches the object
+    fn match_term(&self, term: &Term) -> Result<bool, Error>;
+
+    /// Evaluates a parsed query against the object
+    fn matches(&self, query: &Query) -> Result<bool, Error> {
+        query.eval(&|term| self.match_term(term))
+    }
+
+    /// Parses and evaluates a query, see `Query::parse` for its syntax
+    fn query(&self, query: String) -> Result<bool, Error> {
+        self.matches(&Query::parse(&query)?)
+    }
 }
 
-// type Tags
+/// Tags, either plain or `key=value`, as given on the command line. See
+/// `Tags::into_fields` for how they are set as metadata fields.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Tags {
     pub values: Vec<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Metadata describes the file a pointer refers to, with typed fields set by
+/// the user and the attributes of the file. It is kept in the encrypted
+/// manifest of an encrypted pointer, so it is only readable with the pointer
+/// key.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Metadata {
+    /// CID of the encoded fields, independent of the order they were set in
     pub cid: String,
-    pub encoded_tags: Vec<u8>,
+    /// Fields encoded as canonical DAG-CBOR or, once encrypted, the ciphertext
+    /// of the whole metadata
+    #[serde(alias = "encoded_tags")]
+    pub encoded_fields: Vec<u8>,
+    #[serde(default)]
+    pub nonce: Vec<u8>,
+    /// Name of the original file
+    #[serde(default)]
+    pub filename: Option<String>,
+    /// Size in bytes of the plaintext
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    /// Creation time, in seconds since the Unix epoch
+    #[serde(default)]
+    pub created: Option<u64>,
 }
 
+/// Matches the fields of the metadata, and the file attributes as fields
+/// named `filename`, `mime_type`, `size` and `created`. A field which is not
+/// set does not match any comparison.
 impl Match for Metadata {
-    fn query(&self, query: String) -> Result<bool, String> {
-        // decode tags
-        let tags: Tags = serde_json::from_slice(&self.encoded_tags).unwrap();
-
-        for tag in tags.values {
-            if tag == query {
-                return Ok(true);
-            };
-        }
-        return Ok(false);
+    fn match_term(&self, term: &Term) -> Result<bool, Error> {
+        self.match_fields(&self.fields()?, term)
+    }
+
+    fn matches(&self, query: &Query) -> Result<bool, Error> {
+        // decodes the fields once for the whole query
+        let fields = self.fields()?;
+        query.eval(&|term| self.match_fields(&fields, term))
     }
 }
 
 impl Metadata {
-    pub fn new(tags: Tags) -> Result<Self, String> {
-        let encoded_tags = serde_json::to_vec(&tags).unwrap();
+    pub fn new(tags: Tags) -> Result<Self, Error> {
+        Metadata::from_fields(tags.into_fields())
+    }
 
-        let h = Code::Sha2_256.digest(&encoded_tags);
-        let cid = match Cid::new(Version::V1, SHA256_CODE, h) {
-            Ok(c) => c,
-            Err(e) => return Err(e.to_string()),
-        };
+    pub fn from_fields(fields: Fields) -> Result<Self, Error> {
+        let encoded_fields = encode_fields(&fields)?;
 
         Ok(Metadata {
-            cid: cid.to_string(),
-            encoded_tags,
+            cid: fields_cid(&encoded_fields)?,
+            encoded_fields,
+            nonce: vec![],
+            filename: None,
+            size: 0,
+            mime_type: None,
+            created: None,
         })
     }
 
-    pub fn encrypt(self, key: &[u8; 32]) -> Result<Self, String> {
-        let nonce = GenericArray::from_slice(&self.cid.as_bytes()[0..NONCE_SIZE_BYTES]);
-        let k = GenericArray::from_slice(key);
-        let cipher = Aes256Gcm::new(k);
+    /// Decodes the fields of a plaintext metadata.
+    pub fn fields(&self) -> Result<Fields, Error> {
+        if !self.nonce.is_empty() {
+            return Err(Error::Decode(
+                "Metadata is encrypted, decrypt it or query its tag tokens".to_string(),
+            ));
+        }
+        decode_fields(&self.encoded_fields)
+    }
+
+    /// Returns the field `key`, or the file attribute if `key` is the name of
+    /// one.
+    pub fn field(&self, key: &str) -> Result<Option<Value>, Error> {
+        match self.attribute(key) {
+            Some(attribute) => Ok(attribute),
+            None => Ok(self.fields()?.remove(key)),
+        }
+    }
+
+    fn attribute(&self, key: &str) -> Option<Option<Value>> {
+        let value = match key {
+            "filename" => self.filename.as_deref().map(Value::from),
+            "mime_type" => self.mime_type.as_deref().map(Value::from),
+            "size" => Some(Value::Integer(self.size as i64)),
+            "created" => self.created.map(Value::Timestamp),
+            _ => return None,
+        };
+        Some(value)
+    }
 
-        let enc_tags = cipher.encrypt(nonce, self.encoded_tags.as_slice()).unwrap();
+    fn match_fields(&self, fields: &Fields, term: &Term) -> Result<bool, Error> {
+        let (key, op, value) = match term {
+            Term::Tag(pattern) => (TAGS_FIELD, Cmp::Eq, pattern),
+            Term::Field { key, op, value } => (key.as_str(), *op, value),
+        };
+
+        let field = match is_attribute(key) {
+            true => self.attribute(key).flatten(),
+            false => fields.get(key).cloned(),
+        };
+        Ok(field.is_some_and(|f| f.compare(op, value)))
+    }
+
+    /// Returns the blind index tokens of the plain tags and of the fields under
+    /// the tag tokens key, see `tag_tokens_key`. A field is tokenized as
+    /// `key=value` with the canonical text of its value, see
+    /// `Value::token_text`, and each value of a list field separately.
+    pub fn tag_tokens(&self, tokens_key: &Key) -> Result<Vec<String>, Error> {
+        let mut tokens = vec![];
+        for (key, value) in self.fields()? {
+            for text in value.token_text() {
+                let tag = match key.as_str() {
+                    TAGS_FIELD => text,
+                    _ => format!("{}={}", key, text),
+                };
+                tokens.push(tag_token(tokens_key, &tag));
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// Creates the metadata of the file at `path`, with its name and MIME type
+    /// guessed from its extension. The creation time is set to now, and the
+    /// size once the file data is stored.
+    pub fn for_file(path: &Path, tags: Tags) -> Result<Self, Error> {
+        let mut metadata = Metadata::new(tags)?;
+        metadata.filename = path.file_name().map(|n| n.to_string_lossy().into_owned());
+        metadata.mime_type = path
+            .extension()
+            .and_then(|ext| mime_type(&ext.to_string_lossy()))
+            .map(|m| m.to_string());
+        metadata.created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs());
+
+        Ok(metadata)
+    }
+
+    /// Encrypts the metadata with AES-256-GCM, under the metadata key derived
+    /// from `key`. The encrypted metadata is addressed by the CID of the
+    /// ciphertext, so that it does not reveal the hash of the tags.
+    pub fn encrypt(self, key: &Key) -> Result<Self, Error> {
+        self.encrypt_with(Cipher::default(), key)
+    }
+
+    /// Encrypts the metadata with `cipher`, as `encrypt` does.
+    pub fn encrypt_with(self, cipher: Cipher, key: &Key) -> Result<Self, Error> {
+        let raw = serde_cbor::to_vec(&self).map_err(|e| Error::Encode(e.to_string()))?;
+
+        let nonce = cipher.random_nonce();
+        let ctext = cipher
+            .seal(&metadata_key(key), &nonce, &raw, &[])
+            .ok_or_else(|| Error::Encode("Error encrypting metadata".to_string()))?;
 
         Ok(Metadata {
-            cid: self.cid + "/encrypted",
-            encoded_tags: enc_tags,
+            cid: cid_of(&ctext)?,
+            encoded_fields: ctext,
+            nonce,
+            filename: None,
+            size: 0,
+            mime_type: None,
+            created: None,
         })
     }
 
-    pub fn decrypt(self, key: &[u8; 32]) -> Result<Self, String> {
-        let cid_split: Vec<&str> = self.cid.split('/').collect();
-        let cid = cid_split[0].to_string();
+    pub fn decrypt(self, key: &Key) -> Result<Self, Error> {
+        self.decrypt_with(Cipher::default(), key)
+    }
+
+    /// Decrypts metadata encrypted with `cipher`. Fails with `Error::WrongKey`
+    /// if it was encrypted with another key or cipher.
+    pub fn decrypt_with(self, cipher: Cipher, key: &Key) -> Result<Self, Error> {
+        if self.nonce.len() != cipher.nonce_size() {
+            return Err(Error::Decode("Metadata has an invalid nonce".to_string()));
+        }
+
+        let raw = cipher
+            .open(&metadata_key(key), &self.nonce, &self.encoded_fields, &[])
+            .ok_or(Error::WrongKey)?;
 
-        let nonce = GenericArray::from_slice(&cid.as_bytes()[0..NONCE_SIZE_BYTES]);
-        let key = GenericArray::from_slice(key);
-        let cipher = Aes256Gcm::new(key);
+        serde_cbor::from_slice(&raw)
+            .map_err(|e| Error::Decode(format!("Error decoding metadata: {}", e)))
+    }
+}
 
-        let encoded_tags = cipher.decrypt(nonce, self.encoded_tags.as_slice()).unwrap();
+/// Deserializes an optional metadata, reading the placeholder bytes which
+/// pointers stored before metadata was populated hold as no metadata.
+pub(super) fn optional_metadata<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Metadata>, D::Error> {
+    use serde::de::Error as _;
+    use serde_cbor::Value;
 
-        Ok(Metadata { cid, encoded_tags })
+    match Value::deserialize(deserializer)? {
+        Value::Null | Value::Bytes(_) => Ok(None),
+        value => serde_cbor::value::from_value(value).map_err(D::Error::custom),
     }
 }
 
+/// Guesses the MIME type of a file from its extension.
+fn mime_type(extension: &str) -> Option<&'static str> {
+    let mime = match extension.to_ascii_lowercase().as_str() {
+        "txt" | "md" => "text/plain",
+        "html" | "htm" => "text/html",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// CID of the encoded fields of a plaintext metadata
+fn fields_cid(encoded_fields: &[u8]) -> Result<String, Error> {
+    let h = Code::Sha2_256.digest(encoded_fields);
+    let cid = Cid::new(Version::V1, DAG_CBOR_CODE, h).map_err(|e| Error::Encode(e.to_string()))?;
+    Ok(cid.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,14 +279,33 @@ mod tests {
             values: vec!["hello".to_string(), "world".to_string()],
         };
 
-        let expected_tags = serde_json::to_vec(&tags).unwrap();
+        // {"tags": ["hello", "world"]}
+        let mut expected_fields = vec![0xa1, 0x64];
+        expected_fields.extend_from_slice(b"tags");
+        expected_fields.extend_from_slice(&[0x82, 0x65]);
+        expected_fields.extend_from_slice(b"hello");
+        expected_fields.push(0x65);
+        expected_fields.extend_from_slice(b"world");
         let expected_cid =
-            "baejbeibftkdybljjeqmqwdvk2wolyyjvnrfi5zjfgbgcu2u27chuvqc3xq".to_string();
+            "bafyreiaoq5xvi6yl2rmfkjpvljolsdhkpkwu7aywqevuhj6atkacdjjxtu".to_string();
 
         let m = Metadata::new(tags).unwrap();
 
         assert_eq!(expected_cid, m.cid);
-        assert_eq!(expected_tags, m.encoded_tags);
+        assert_eq!(expected_fields, m.encoded_fields);
+
+        // the CID does not depend on the order the fields are set in
+        let tags = |values: &[&str]| Tags {
+            values: values.iter().map(|v| v.to_string()).collect(),
+        };
+        assert_eq!(
+            Metadata::new(tags(&["author=alice", "year=2026", "hello"]))
+                .unwrap()
+                .cid,
+            Metadata::new(tags(&["hello", "year=2026", "author=alice"]))
+                .unwrap()
+                .cid
+        );
     }
 
     #[test]
@@ -111,27 +316,196 @@ mod tests {
 
         let meta = Metadata::new(tags).unwrap();
 
-        assert!(meta.query("hello".to_string()).unwrap() == true);
-        assert!(meta.query("world".to_string()).unwrap() == true);
-        assert!(meta.query("moon".to_string()).unwrap() == false);
+        assert!(meta.query("hello".to_string()).unwrap());
+        assert!(meta.query("world".to_string()).unwrap());
+        assert!(!meta.query("moon".to_string()).unwrap());
+
+        // undecodable tags are an error rather than a panic
+        let corrupt = Metadata {
+            encoded_fields: vec![0xff],
+            ..meta
+        };
+        assert!(matches!(
+            corrupt.query("hello".to_string()),
+            Err(Error::Decode(_))
+        ));
     }
 
     #[test]
-    fn encrypt_decrypt() {
+    fn metadata_query() {
+        let tags = Tags {
+            values: vec![
+                "invoice".to_string(),
+                "author=alice".to_string(),
+                "year=2026".to_string(),
+            ],
+        };
+        let mut meta = Metadata::for_file(Path::new("bills/march.pdf"), tags).unwrap();
+        meta.size = 2_000_000;
+        meta.created = Some(1767270600); // 2026-01-01T12:30:00Z
+
+        for (query, expected) in [
+            ("inv* AND author=alice", true),
+            ("invoice NOT author=bob", true),
+            ("author=al*", true),
+            ("year>=2020 AND year<2030", true),
+            ("year>2026", false),
+            ("filename=*.pdf mime=application/pdf", true),
+            ("name!=*.pdf", false),
+            ("size>1MB AND size<=2MB", true),
+            ("size<1MiB", false),
+            ("created>=2026-01-01 created<2026-01-02", true),
+            ("created<2025-12-31", false),
+        ] {
+            assert_eq!(
+                meta.query(query.to_string()).unwrap(),
+                expected,
+                "{}",
+                query
+            );
+        }
+
+        // metadata without a creation time never matches a date range
+        meta.created = None;
+        assert!(!meta.query("created>0".to_string()).unwrap());
+    }
+
+    #[test]
+    fn typed_fields() {
+        let mut fields = Fields::new();
+        fields.insert("due".to_string(), Value::Timestamp(1767225600));
+        fields.insert("digest".to_string(), Value::Bytes(vec![0xca, 0xfe]));
+        fields.insert("pages".to_string(), Value::Integer(12));
+        fields.insert(
+            "authors".to_string(),
+            Value::List(vec![Value::from("alice"), Value::from("bob")]),
+        );
+        let mut meta = Metadata::from_fields(fields.clone()).unwrap();
+        meta.size = 42;
+
+        assert_eq!(meta.fields().unwrap(), fields);
+        assert_eq!(meta.field("pages").unwrap(), Some(Value::Integer(12)));
+        assert_eq!(meta.field("size").unwrap(), Some(Value::Integer(42)));
+        assert_eq!(meta.field("filename").unwrap(), None);
+        assert_eq!(meta.field("missing").unwrap(), None);
+
+        for (query, expected) in [
+            ("due<2026-01-02 due>=2026-01-01", true),
+            ("due>2026-01-01T00:00:00", false),
+            ("digest=cafe", true),
+            ("pages>10 pages<=12", true),
+            // a string is not an integer
+            ("pages=twelve", false),
+            ("authors=bob NOT authors=carol", true),
+            ("authors!=alice", false),
+            ("missing=*", false),
+            ("missing!=x", true),
+        ] {
+            assert_eq!(
+                meta.query(query.to_string()).unwrap(),
+                expected,
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn for_file() {
+        let tags = Tags {
+            values: vec!["hello".to_string()],
+        };
+        let meta = Metadata::for_file(Path::new("docs/Photo.JPG"), tags).unwrap();
+        assert_eq!(meta.filename, Some("Photo.JPG".to_string()));
+        assert_eq!(meta.mime_type, Some("image/jpeg".to_string()));
+        assert!(meta.created.is_some());
+        assert!(meta.query("hello".to_string()).unwrap());
+
+        let tags = Tags { values: vec![] };
+        let meta = Metadata::for_file(Path::new(""), tags).unwrap();
+        assert_eq!(meta.filename, None);
+        assert_eq!(meta.mime_type, None);
+    }
+
+    #[test]
+    fn tag_tokens() {
         let tags = Tags {
             values: vec!["hello".to_string(), "world".to_string()],
         };
         let meta = Metadata::new(tags).unwrap();
 
-        let key = b"hello darkness my good ol friend";
+        let search_key = &Key::from(b"hello darkness my good ol friend");
+        let tokens = meta.tag_tokens(search_key).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                tag_token(search_key, "hello"),
+                tag_token(search_key, "world")
+            ]
+        );
+
+        // every field is tokenized, and matched by the query of its tag
+        let tags = Tags {
+            values: vec![
+                "due=2026-01-01".to_string(),
+                "id=007".to_string(),
+                "author=alice".to_string(),
+                "tags=draft".to_string(),
+            ],
+        };
+        let mut fields = Metadata::new(tags).unwrap().fields().unwrap();
+        fields.insert("digest".to_string(), Value::Bytes(vec![0xca, 0xfe]));
+        let meta = Metadata::from_fields(fields).unwrap();
+        let tokens = meta.tag_tokens(search_key).unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert!(meta.query("tags=draft".to_string()).unwrap());
+        for query in [
+            "tags=draft",
+            "draft",
+            "due=2026-01-01",
+            "due=2026-01-01T00:00:00",
+            "id=7",
+            "author=alice",
+            "digest=cafe",
+        ] {
+            let query = Query::parse(query).unwrap().tag_tokens(search_key).unwrap();
+            match query {
+                Query::Term(Term::Tag(token)) => assert!(tokens.contains(&token)),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn encrypt_decrypt() {
+        let tags = Tags {
+            values: vec!["hello".to_string(), "world".to_string()],
+        };
+        let mut meta = Metadata::new(tags).unwrap();
+        meta.filename = Some("hello.txt".to_string());
+        meta.size = 42;
+
+        let key = &Key::from(b"hello darkness my good ol friend");
         let encrypted_meta = meta.clone().encrypt(key).unwrap();
 
-        assert_eq!(meta.cid.clone() + &"/encrypted", encrypted_meta.cid);
-        assert_ne!(meta.encoded_tags, encrypted_meta.encoded_tags);
+        assert_ne!(meta.cid, encrypted_meta.cid);
+        assert_ne!(meta.encoded_fields, encrypted_meta.encoded_fields);
+        assert_eq!(encrypted_meta.filename, None);
+
+        // encrypted tags cannot be queried
+        assert!(matches!(
+            encrypted_meta.query("hello".to_string()),
+            Err(Error::Decode(_))
+        ));
 
         let decrypted_meta = encrypted_meta.decrypt(key).unwrap();
 
-        assert_eq!(meta.cid, decrypted_meta.cid);
-        assert_eq!(meta.encoded_tags, decrypted_meta.encoded_tags);
+        assert_eq!(meta, decrypted_meta);
+
+        let encrypted_meta = meta.encrypt(key).unwrap();
+        assert!(matches!(
+            encrypted_meta.decrypt(&Key::from(b"another very very secret key....")),
+            Err(Error::WrongKey)
+        ));
     }
 }
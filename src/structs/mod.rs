@@ -1,54 +1,191 @@
-#![allow(dead_code)]
 #![allow(unused_must_use)]
 
+mod builder;
+mod chunker;
+mod index;
+mod legacy;
+mod manifest;
 mod metadata;
+mod query;
+mod reader;
+mod value;
+
+pub use builder::PointerBuilder;
+pub use chunker::Chunker;
+pub use index::TagIndex;
+pub use metadata::{Match, Metadata, Tags};
+pub use query::{Cmp, Query, Term};
+pub use reader::PointerReader;
+pub use value::{Fields, Value};
+
+use manifest::Manifest;
+use metadata::optional_metadata;
 
-use aes_gcm::aead::{generic_array::GenericArray, Aead, NewAead};
-use aes_gcm::Aes256Gcm;
 use cid::{Cid, Version};
 use multihash::{Code, MultihashDigest};
 use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
-
-const MAX_BLOCK_SIZE: usize = 256 * 8; // 256 bytes
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::backend::Backend;
+use crate::crypto::{
+    blocks_key, convergent_key, key_commitment, member_token, metadata_key, random_key,
+    random_stream_id, verify_key_commitment, Cipher, Identity, KdfParams, Key, Recipient, Stanza,
+    WrappedKey,
+};
+use crate::error::Error;
+
+/// Block size used when none is set, 256 KiB
+pub const DEFAULT_BLOCK_SIZE: usize = 256 * 1024;
+/// Smallest block size which can be configured, 64 bytes
+pub const MIN_BLOCK_SIZE: usize = 64;
+/// Largest block size, 16 MiB
+pub const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+/// Block size of pointers created before the block size was recorded in the
+/// pointer, 2 KiB
+pub const LEGACY_BLOCK_SIZE: usize = 2048;
+/// Format version of the pointers created by this version of en-crypt. Only
+/// pointers of this version can be decrypted; pointers stored with an earlier
+/// format must be stored again.
+///
+/// - 1: key commitment
+/// - 2: keys derived per purpose with HKDF, including the key commitment
+pub const FORMAT_VERSION: u32 = 2;
 const SHA256_CODE: u64 = 0x12;
-const NONCE_SIZE_BYTES: usize = 12;
 
+/// Wrapper holds the fields of a pointer. In an encrypted pointer, the fields
+/// which describe the plaintext (`metadata`, `head_block`, `lengths` and the
+/// plaintext block CIDs) are kept in the encrypted `manifest` instead, and
+/// `cid` is the CID of the encrypted manifest.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct Wrapper {
+    /// Format version of the pointer, see `FORMAT_VERSION`. 0 in pointers
+    /// stored before the format was versioned
+    #[serde(default)]
+    pub version: u32,
     pub cid: String,
-    pub metadata: Box<[u8]>,
+    #[serde(default, deserialize_with = "optional_metadata")]
+    pub metadata: Option<Metadata>,
+    /// Encrypted `Manifest` of an encrypted pointer
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub manifest: Vec<u8>,
+    #[serde(default)]
+    pub manifest_nonce: Vec<u8>,
     pub head_block: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub blocks: Vec<Block>,
+    /// CIDs of the stored blocks, in order, once the pointer is persisted in a
+    /// backend
+    #[serde(default)]
+    pub links: Vec<String>,
+    /// Size in bytes of the plaintext of each block, in order
+    #[serde(default)]
+    pub lengths: Vec<u64>,
+    /// How the data was split in blocks
+    pub chunker: Chunker,
+    /// Cipher the blocks and manifest are encrypted with
+    pub cipher: Cipher,
+    #[serde(default)]
+    pub kdf: Option<KdfParams>,
+    /// Data key of a pointer encrypted with a passphrase, wrapped with the key
+    /// derived from the passphrase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_key: Option<WrappedKey>,
+    /// Data key of a pointer encrypted to recipients, wrapped to each of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<Stanza>,
+    /// Random identifier of an encrypted pointer, bound to each of its blocks.
+    /// Empty in plaintext pointers
+    #[serde(default)]
+    pub stream_id: Vec<u8>,
+    /// Commitment of an encrypted pointer to its data key, so that it cannot
+    /// be crafted to decrypt under two keys. See `crypto::key_commitment`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_commitment: Vec<u8>,
+    /// Blind index tokens of the metadata tags of a searchable pointer, kept
+    /// in clear so that they can be matched without any key
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tag_tokens: Vec<String>,
+    /// Read position over the blocks data
+    #[serde(skip)]
+    pub pos: u64,
 }
 
-impl Read for Wrapper {
-    fn read(&mut self, _buf: &mut [u8]) -> Result<usize, std::io::Error> {
-        unimplemented!();
-    }
+/// Associated data of the block at `index` of a pointer, which binds the block
+/// to the pointer, to its position and to whether it is the last block, as in
+/// the STREAM construction. Blocks can then not be reordered, dropped or
+/// swapped with blocks of other pointers without failing authentication.
+fn block_ad(stream_id: &[u8], index: usize, last: bool) -> Vec<u8> {
+    let mut ad = stream_id.to_vec();
+    ad.extend_from_slice(&(index as u64).to_be_bytes());
+    ad.push(last as u8);
+    ad
+}
 
-    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, std::io::Error> {
-        let mut count_bytes: usize = 0;
+impl Read for Wrapper {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        let mut offset: u64 = 0;
 
         for block in &self.blocks {
-            let mut tmp_buf: Vec<u8> = vec![];
-            let bytes = block.data.as_ref().read_to_end(&mut tmp_buf).unwrap();
-            buf.extend_from_slice(tmp_buf.as_slice());
-            count_bytes += bytes;
+            let len = block.data.len() as u64;
+            if self.pos < offset + len {
+                let start = (self.pos - offset) as usize;
+                let n = std::cmp::min(buf.len(), block.data.len() - start);
+                buf[..n].copy_from_slice(&block.data[start..start + n]);
+                self.pos += n as u64;
+                return Ok(n);
+            }
+            offset += len;
         }
-        Ok(count_bytes)
+        Ok(0)
     }
 }
 
+impl Seek for Wrapper {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, std::io::Error> {
+        let len = self.blocks.iter().map(|b| b.data.len() as u64).sum();
+        self.pos = seek_position(self.pos, len, pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// Computes the new position of a cursor over `len` bytes after a seek.
+fn seek_position(current: u64, len: u64, pos: SeekFrom) -> Result<u64, std::io::Error> {
+    let new_pos = match pos {
+        SeekFrom::Start(n) => Some(n),
+        SeekFrom::End(n) => len.checked_add_signed(n),
+        SeekFrom::Current(n) => current.checked_add_signed(n),
+    };
+
+    match new_pos {
+        Some(p) => Ok(p),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )),
+    }
+}
+
+/// Block is a chunk of the data a pointer refers to, addressed by the CID of
+/// its plaintext.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct Block {
+pub struct Block {
     pub cid: String,
     pub next: Option<String>,
     pub data: Box<[u8]>,
+    #[serde(default)]
+    pub nonce: Vec<u8>,
+    /// Largest data the block can be written with, not serialised
+    #[serde(skip, default = "max_block_size")]
+    max_size: usize,
+}
+
+fn max_block_size() -> usize {
+    MAX_BLOCK_SIZE
 }
 
 impl Block {
-    pub fn into_raw(&self) -> &[u8] {
+    pub fn as_raw(&self) -> &[u8] {
         &self.data
     }
 
@@ -57,44 +194,96 @@ impl Block {
             cid: "".to_string(),
             next: None,
             data: Box::new([]),
+            nonce: vec![],
+            max_size: MAX_BLOCK_SIZE,
         }
     }
 
-    pub fn encrypt(self, key: &[u8; 32]) -> Block {
-        let nonce = GenericArray::from_slice(&self.cid.as_bytes()[0..NONCE_SIZE_BYTES]);
-        let k = GenericArray::from_slice(key);
-        let cipher = Aes256Gcm::new(k);
-
-        let ctext = cipher.encrypt(nonce, self.data.as_ref()).unwrap();
-
-        let mut enc_data: Vec<u8> = vec![];
-        enc_data.extend_from_slice(&ctext);
-
+    /// Returns an empty block of a pointer split with `chunker`, which can be
+    /// written with at most the largest block size of the chunker.
+    pub fn for_chunker(chunker: &Chunker) -> Self {
         Block {
-            cid: self.cid + "/encrypted",
-            next: self.next,
-            data: enc_data.into_boxed_slice(),
+            max_size: chunker.max_size(),
+            ..Block::new_empty()
         }
     }
 
-    pub fn decrypt(self, key: &[u8; 32]) -> Block {
-        let cid_split: Vec<&str> = self.cid.split('/').collect();
-        let cid = cid_split[0].to_string();
+    /// Encrypts the block with AES-256-GCM, authenticating `ad` together with
+    /// the data. See `block_ad` for the associated data of the blocks of a
+    /// pointer. The encrypted block is addressed by the CID of its ciphertext.
+    pub fn encrypt(self, key: &Key, ad: &[u8]) -> Result<Block, Error> {
+        self.encrypt_with(Cipher::default(), key, ad)
+    }
+
+    /// Encrypts the block with `cipher`, as `encrypt` does.
+    pub fn encrypt_with(self, cipher: Cipher, key: &Key, ad: &[u8]) -> Result<Block, Error> {
+        let nonce = cipher.random_nonce();
+        self.seal(cipher, key, nonce, ad)
+    }
 
-        let nonce = GenericArray::from_slice(&cid.as_bytes()[0..NONCE_SIZE_BYTES]);
-        let key = GenericArray::from_slice(key);
-        let cipher = Aes256Gcm::new(key);
+    /// Encrypts the block in convergent mode, with a key derived from its
+    /// content, so that the same block always encrypts to the same ciphertext
+    /// and is stored once. Returns the encrypted block and its key.
+    pub fn encrypt_convergent(self) -> Result<(Block, Key), Error> {
+        self.encrypt_convergent_with(Cipher::default())
+    }
 
-        let ptext = cipher.decrypt(nonce, self.data.as_ref()).unwrap();
+    /// Encrypts the block in convergent mode with `cipher`. Every convergent
+    /// key encrypts a single plaintext, so the fixed nonce is never reused
+    /// with different data.
+    pub fn encrypt_convergent_with(self, cipher: Cipher) -> Result<(Block, Key), Error> {
+        let key = convergent_key(&self.data);
+        let block = self.seal(cipher, &key, cipher.zero_nonce(), &[])?;
+        Ok((block, key))
+    }
 
-        let mut data: Vec<u8> = vec![];
-        data.extend_from_slice(&ptext.as_slice());
+    fn seal(self, cipher: Cipher, key: &Key, nonce: Vec<u8>, ad: &[u8]) -> Result<Block, Error> {
+        let ctext = cipher
+            .seal(key, &nonce, &self.data, ad)
+            .ok_or_else(|| Error::Encode(format!("Error encrypting block {}", self.cid)))?;
 
-        Block {
-            cid: cid,
+        Ok(Block {
+            cid: cid_of(&ctext)?,
             next: self.next,
-            data: data.into_boxed_slice(),
+            data: ctext.into_boxed_slice(),
+            nonce,
+            max_size: self.max_size,
+        })
+    }
+
+    /// Decrypts a block encrypted with `encrypt`. The decrypted block is
+    /// addressed by the CID of its plaintext, which the caller checks against
+    /// the expected block CID. Fails with `Error::IntegrityMismatch` if the
+    /// ciphertext does not match the block CID, and with
+    /// `Error::Authentication` if the block was tampered with, or encrypted
+    /// with another key or associated data.
+    pub fn decrypt(self, key: &Key, ad: &[u8]) -> Result<Block, Error> {
+        self.decrypt_with(Cipher::default(), key, ad)
+    }
+
+    /// Decrypts a block encrypted with `cipher`, as `decrypt` does.
+    pub fn decrypt_with(self, cipher: Cipher, key: &Key, ad: &[u8]) -> Result<Block, Error> {
+        if cid_of(&self.data)? != self.cid {
+            return Err(Error::IntegrityMismatch(self.cid));
+        }
+        if self.nonce.len() != cipher.nonce_size() {
+            return Err(Error::CorruptBlock(format!(
+                "Block {} has an invalid nonce",
+                self.cid
+            )));
         }
+
+        let ptext = cipher
+            .open(key, &self.nonce, &self.data, ad)
+            .ok_or_else(|| Error::Authentication(format!("block {}", self.cid)))?;
+
+        Ok(Block {
+            cid: cid_of(&ptext)?,
+            next: self.next,
+            data: ptext.into_boxed_slice(),
+            nonce: vec![],
+            max_size: self.max_size,
+        })
     }
 }
 
@@ -110,23 +299,20 @@ impl Read for Block {
 
 impl Write for Block {
     fn write(&mut self, buf: &[u8]) -> std::result::Result<usize, std::io::Error> {
-        if buf.len() > MAX_BLOCK_SIZE {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!(
-                    "Data too large to store by a single block. Max {:?} bytes",
-                    MAX_BLOCK_SIZE
-                ),
-            ));
+        if buf.len() > self.max_size {
+            return Err(std::io::Error::other(format!(
+                "Data too large to store by a single block. Max {:?} bytes",
+                self.max_size
+            )));
         }
 
         let mut new_data: Vec<u8> = vec![];
-        new_data.extend_from_slice(&buf);
+        new_data.extend_from_slice(buf);
 
         let h = Code::Sha2_256.digest(&new_data);
         let cid = match Cid::new(Version::V1, SHA256_CODE, h) {
             Ok(c) => c,
-            Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+            Err(e) => return Err(std::io::Error::other(e)),
         };
 
         self.cid = cid.to_string();
@@ -141,141 +327,665 @@ impl Write for Block {
     }
 }
 
+/// Computes the CIDv1 (sha2-256) of raw bytes.
+pub fn cid_of(data: &[u8]) -> Result<String, Error> {
+    let h = Code::Sha2_256.digest(data);
+    match Cid::new(Version::V1, SHA256_CODE, h) {
+        Ok(c) => Ok(c.to_string()),
+        Err(e) => Err(Error::Encode(e.to_string())),
+    }
+}
+
+/// Computes the CID of a pointer from the CIDs of its blocks, in order.
+fn root_cid<S: AsRef<str>>(block_cids: &[S]) -> Result<String, Error> {
+    let mut concat_block_cids = vec![];
+    for cid in block_cids {
+        concat_block_cids.extend_from_slice(cid.as_ref().as_bytes());
+    }
+    cid_of(&concat_block_cids)
+}
+
+/// Pointer links the blocks of a file together with its metadata. A pointer is
+/// either plaintext, as created by `Pointer::from`, or encrypted.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Pointer(Wrapper);
 
 impl Read for Pointer {
-    fn read(&mut self, _buf: &mut [u8]) -> Result<usize, std::io::Error> {
-        unimplemented!();
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        self.0.read(buf)
     }
+}
 
-    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, std::io::Error> {
-        self.0.read_to_end(buf)
+impl Seek for Pointer {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, std::io::Error> {
+        self.0.seek(pos)
+    }
+}
+
+/// Matches tag tokens, as computed with `tag_token` or `Query::tag_tokens`,
+/// against the tag tokens of a searchable pointer. Neither the data key nor the
+/// search key is needed, so an encrypted pointer header can be matched by a
+/// backend or index server.
+impl Match for Pointer {
+    fn match_term(&self, term: &Term) -> Result<bool, Error> {
+        let token = term.token()?;
+        Ok(self.0.tag_tokens.iter().any(|t| t == token))
     }
 }
 
 impl Pointer {
-    pub fn from(buf: &[u8]) -> Result<Self, String> {
-        let mut chunker = buf.chunks(MAX_BLOCK_SIZE);
+    pub fn from(buf: &[u8]) -> Result<Self, Error> {
+        Pointer::from_with_block_size(buf, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Creates a pointer splitting `buf` in blocks of at most `block_size`
+    /// bytes.
+    pub fn from_with_block_size(buf: &[u8], block_size: usize) -> Result<Self, Error> {
+        Pointer::from_with_chunker(buf, Chunker::Fixed { size: block_size })
+    }
+
+    /// Creates a pointer splitting `buf` in blocks with `chunker`.
+    pub fn from_with_chunker(buf: &[u8], chunker: Chunker) -> Result<Self, Error> {
+        chunker.validate()?;
 
         let mut blocks = Vec::<Block>::new();
+        let mut lengths = vec![];
         let mut concat_block_cids = vec![];
         let mut head_block = None;
 
-        let mut idx = 0;
-        loop {
-            let chunk = match chunker.next() {
-                Some(c) => c,
-                None => break,
-            };
-            let mut data = Vec::new();
-            data.extend_from_slice(&chunk);
-
-            let mut block = Block::new_empty();
-            block.write(&mut data);
+        for (idx, chunk) in chunker.split(buf).into_iter().enumerate() {
+            let mut block = Block::for_chunker(&chunker);
+            block.write_all(chunk)?;
 
             if idx == 0 {
                 head_block = Some(block.cid.to_string());
             }
 
             concat_block_cids.append(&mut Vec::from(block.cid.to_string()));
+            lengths.push(block.data.len() as u64);
             blocks.push(block);
-            idx += 1;
         }
 
-        let h = Code::Sha2_256.digest(&concat_block_cids);
-        let cid = match Cid::new(Version::V1, SHA256_CODE, h) {
-            Ok(c) => c,
-            Err(e) => return Err(e.to_string()),
-        };
+        let cid = cid_of(&concat_block_cids)?;
 
         let wrapper = Wrapper {
-            cid: cid.to_string(),
+            version: FORMAT_VERSION,
+            cid,
             blocks,
+            links: vec![],
+            lengths,
+            chunker,
+            cipher: Cipher::default(),
             head_block,
-            metadata: Box::new([0]),
+            metadata: None,
+            manifest: vec![],
+            manifest_nonce: vec![],
+            kdf: None,
+            wrapped_key: None,
+            recipients: vec![],
+            stream_id: vec![],
+            key_commitment: vec![],
+            tag_tokens: vec![],
+            pos: 0,
         };
 
-        Ok(Pointer { 0: wrapper })
+        Ok(Pointer(wrapper))
     }
 
-    pub fn encrypt(self, key: &[u8; 32]) -> Result<Pointer, String> {
-        let nonce = GenericArray::from_slice(&self.0.cid.as_bytes()[0..NONCE_SIZE_BYTES]);
-        let k = GenericArray::from_slice(key);
-        let cipher = Aes256Gcm::new(k);
+    /// Encrypts the pointer metadata and blocks, with the metadata key and the
+    /// blocks key derived from `key`. Each block is bound to the pointer and to
+    /// its position with `block_ad`. The plaintext CIDs are only kept in the
+    /// encrypted manifest, so that the encrypted pointer does not reveal the
+    /// hashes of its content.
+    pub fn encrypt(self, key: &Key) -> Result<Pointer, Error> {
+        self.encrypt_blocks(key, false)
+    }
+
+    /// Encrypts the pointer in convergent mode: each block is encrypted with
+    /// a key derived from its content, so that identical blocks are stored
+    /// once across pointers and users. This reveals whether a stored pointer
+    /// contains a known block, so it must be opted into explicitly. The
+    /// pointer manifest, which holds the block keys, is encrypted with `key`.
+    pub fn encrypt_convergent(self, key: &Key) -> Result<Pointer, Error> {
+        self.encrypt_blocks(key, true)
+    }
 
-        let metadata = self.0.metadata.as_ref();
-        let ctext = cipher.encrypt(nonce, metadata).unwrap();
+    fn encrypt_blocks(self, key: &Key, convergent: bool) -> Result<Pointer, Error> {
+        let stream_id = random_stream_id();
+        let blocks_len = self.0.blocks.len();
+        let cipher = self.0.cipher;
+        let blocks_key = blocks_key(key);
 
-        let mut enc_metadata: Vec<u8> = vec![];
-        enc_metadata.extend_from_slice(&ctext);
+        let mut manifest = Manifest {
+            cid: self.0.cid,
+            head_block: self.0.head_block,
+            block_cids: vec![],
+            lengths: self.0.lengths,
+            block_keys: vec![],
+            metadata: self.0.metadata,
+        };
 
         let mut enc_blocks: Vec<Block> = vec![];
+        for (idx, block) in self.0.blocks.into_iter().enumerate() {
+            manifest.block_cids.push(block.cid.clone());
+            if convergent {
+                let (enc_block, block_key) = block.encrypt_convergent_with(cipher)?;
+                manifest.block_keys.push(block_key);
+                enc_blocks.push(enc_block);
+            } else {
+                let ad = block_ad(&stream_id, idx, idx + 1 == blocks_len);
+                enc_blocks.push(block.encrypt_with(cipher, &blocks_key, &ad)?);
+            }
+        }
+
+        let mut pointer = Pointer::encrypted(self.0.chunker, cipher, stream_id);
+        pointer.0.blocks = enc_blocks;
+        pointer.0.tag_tokens = self.0.tag_tokens;
+        pointer.seal_manifest(&manifest, key)?;
+
+        Ok(pointer)
+    }
 
-        // encrypt blocks
-        for block in self.0.blocks {
-            let enc_block = block.encrypt(key);
-            enc_blocks.push(enc_block);
+    /// Returns an empty encrypted pointer, to be sealed with `seal_manifest`.
+    fn encrypted(chunker: Chunker, cipher: Cipher, stream_id: Vec<u8>) -> Pointer {
+        Pointer(Wrapper {
+            version: FORMAT_VERSION,
+            cid: String::new(),
+            metadata: None,
+            manifest: vec![],
+            manifest_nonce: vec![],
+            head_block: None,
+            blocks: vec![],
+            links: vec![],
+            lengths: vec![],
+            chunker,
+            cipher,
+            kdf: None,
+            wrapped_key: None,
+            recipients: vec![],
+            stream_id,
+            key_commitment: vec![],
+            tag_tokens: vec![],
+            pos: 0,
+        })
+    }
+
+    /// Encrypts the manifest of an encrypted pointer with the metadata key
+    /// derived from the data key `key`, and commits the pointer to `key`. The
+    /// pointer is then addressed by the CID of the encrypted manifest.
+    fn seal_manifest(&mut self, manifest: &Manifest, key: &Key) -> Result<(), Error> {
+        let (ctext, nonce) = manifest.encrypt(
+            self.0.cipher,
+            &metadata_key(key),
+            &self.0.stream_id,
+            self.blocks_len(),
+        )?;
+        self.0.cid = cid_of(&ctext)?;
+        self.0.manifest = ctext;
+        self.0.manifest_nonce = nonce;
+        self.0.key_commitment = key_commitment(key, &self.0.stream_id);
+        Ok(())
+    }
+
+    /// Checks and decrypts the manifest of an encrypted pointer. The key
+    /// commitment is checked first, so that nothing is decrypted with a key
+    /// the pointer does not commit to.
+    fn open_manifest(&self, key: &Key) -> Result<Manifest, Error> {
+        self.check_version()?;
+        if self.0.manifest.is_empty() {
+            return Err(Error::Decode(
+                "Pointer is not encrypted, or was encrypted with an unsupported format".to_string(),
+            ));
+        }
+        if cid_of(&self.0.manifest)? != self.0.cid {
+            return Err(Error::IntegrityMismatch(self.0.cid.clone()));
+        }
+        if !verify_key_commitment(key, &self.0.stream_id, &self.0.key_commitment) {
+            return Err(Error::WrongKey);
         }
 
-        let wrapper = Wrapper {
-            cid: self.0.cid + "/encrypted",
-            head_block: self.0.head_block,
-            metadata: enc_metadata.into_boxed_slice(),
-            blocks: enc_blocks,
-        };
+        Manifest::decrypt(
+            self.0.cipher,
+            &self.0.manifest,
+            &self.0.manifest_nonce,
+            &metadata_key(key),
+            &self.0.stream_id,
+            self.blocks_len(),
+        )
+    }
+
+    /// Decrypts the pointer manifest only, without its blocks, so that the
+    /// metadata and size of the pointer can be read without fetching them.
+    pub fn decrypt_header(mut self, key: &Key) -> Result<Pointer, Error> {
+        let manifest = self.open_manifest(key)?;
+        self.0.blocks = vec![];
+        Ok(self.into_plaintext(manifest, vec![]))
+    }
+
+    /// Deletes a stored pointer and its blocks from a backend, once checked
+    /// that the pointer decrypts with `key`. The blocks of a pointer encrypted
+    /// in convergent mode may be shared with other pointers, so they are kept.
+    pub fn delete(cid: &str, key: &Key, backend: &mut dyn Backend) -> Result<(), Error> {
+        let header = Pointer::load_header(cid, backend)?;
+        let manifest = header.open_manifest(key)?;
+
+        if !manifest.is_convergent() {
+            for link in &header.0.links {
+                backend.delete(link)?;
+            }
+        }
+        backend.delete(cid)
+    }
 
-        Ok(Pointer { 0: wrapper })
+    /// Whether the pointer has the format of this version of en-crypt, see
+    /// `FORMAT_VERSION`. Pointers of other formats cannot be decrypted.
+    pub fn is_supported(&self) -> bool {
+        self.0.version == FORMAT_VERSION
     }
 
-    pub fn decrypt(self, key: &[u8; 32]) -> Result<Pointer, String> {
-        let cid_split: Vec<&str> = self.0.cid.split('/').collect();
-        let cid = cid_split[0].to_string();
+    fn check_version(&self) -> Result<(), Error> {
+        if self.is_supported() {
+            return Ok(());
+        }
+        Err(Error::Decode(format!(
+            "Pointer has format version {}, but only version {} is supported: it must be stored again",
+            self.0.version, FORMAT_VERSION
+        )))
+    }
+
+    /// Whether the pointer is encrypted. Other objects stored in a backend,
+    /// such as blocks, may decode as a plaintext pointer.
+    pub fn is_encrypted(&self) -> bool {
+        !self.0.manifest.is_empty()
+    }
+
+    /// Decrypts the pointer and its blocks. Fails with `Error::WrongKey` if
+    /// the pointer does not commit to `key`, and with `Error::Authentication`
+    /// if its manifest or a block was tampered with. Each block is checked
+    /// against its plaintext CID in the manifest.
+    pub fn decrypt(mut self, key: &Key) -> Result<Pointer, Error> {
+        let manifest = self.open_manifest(key)?;
+        let blocks_key = blocks_key(key);
 
-        let nonce = GenericArray::from_slice(&cid.as_bytes()[0..NONCE_SIZE_BYTES]);
-        let k = GenericArray::from_slice(key);
-        let cipher = Aes256Gcm::new(k);
+        let mut dec_blocks: Vec<Block> = vec![];
+        for (idx, block) in std::mem::take(&mut self.0.blocks).into_iter().enumerate() {
+            dec_blocks.push(manifest.open_block(
+                self.0.cipher,
+                block,
+                &blocks_key,
+                &self.0.stream_id,
+                idx,
+            )?);
+        }
 
-        let ptext = match cipher.decrypt(nonce, self.0.metadata.as_ref()) {
-            Ok(pt) => pt,
-            Err(e) => {
-                println!("{:?}", e);
-                return Err(e.to_string());
+        Ok(self.into_plaintext(manifest, dec_blocks))
+    }
+
+    /// Returns the plaintext pointer described by the decrypted `manifest`.
+    fn into_plaintext(self, manifest: Manifest, blocks: Vec<Block>) -> Pointer {
+        Pointer(Wrapper {
+            version: self.0.version,
+            cid: manifest.cid,
+            metadata: manifest.metadata,
+            manifest: vec![],
+            manifest_nonce: vec![],
+            head_block: manifest.head_block,
+            blocks,
+            links: self.0.links,
+            lengths: manifest.lengths,
+            chunker: self.0.chunker,
+            cipher: self.0.cipher,
+            kdf: self.0.kdf,
+            wrapped_key: self.0.wrapped_key,
+            recipients: self.0.recipients,
+            stream_id: vec![],
+            key_commitment: vec![],
+            tag_tokens: self.0.tag_tokens,
+            pos: 0,
+        })
+    }
+
+    /// Encrypts the pointer with a random data key, wrapped with the
+    /// key-encryption key `kek`. The key-encryption key can then be changed
+    /// with `rewrap`, without re-encrypting the blocks.
+    pub fn encrypt_with_kek(self, kek: &Key) -> Result<Pointer, Error> {
+        let key = random_key();
+        let wrapped_key = WrappedKey::wrap(kek, &key)?;
+
+        let mut pointer = self.encrypt(&key)?;
+        pointer.0.wrapped_key = Some(wrapped_key);
+
+        Ok(pointer)
+    }
+
+    /// Derives a key-encryption key from a passphrase and encrypts the pointer
+    /// with `encrypt_with_kek`. The KDF parameters (including the random salt)
+    /// are kept in the encrypted pointer so that the key can be derived again
+    /// on decryption.
+    pub fn encrypt_with_passphrase(
+        self,
+        passphrase: &str,
+        params: KdfParams,
+    ) -> Result<Pointer, Error> {
+        let kek = params.derive_key(passphrase.as_bytes())?;
+
+        let mut pointer = self.encrypt_with_kek(&kek)?;
+        pointer.0.kdf = Some(params);
+
+        Ok(pointer)
+    }
+
+    /// Decrypts a pointer encrypted with `encrypt_with_passphrase`, deriving
+    /// the key from the passphrase and the KDF parameters stored in the pointer.
+    pub fn decrypt_with_passphrase(self, passphrase: &str) -> Result<Pointer, Error> {
+        let key = self.derive_key(passphrase)?;
+        self.decrypt(&key)
+    }
+
+    /// Returns the data key of a pointer encrypted with
+    /// `encrypt_with_passphrase`, unwrapped with the key derived from the
+    /// passphrase.
+    pub fn derive_key(&self, passphrase: &str) -> Result<Key, Error> {
+        match &self.0.kdf {
+            Some(params) => self.data_key(&params.derive_key(passphrase.as_bytes())?),
+            None => Err(Error::Kdf(
+                "Pointer has no key derivation parameters".to_string(),
+            )),
+        }
+    }
+
+    /// Unwraps the data key of a pointer encrypted with `encrypt_with_kek`.
+    pub fn data_key(&self, kek: &Key) -> Result<Key, Error> {
+        self.check_version()?;
+        match &self.0.wrapped_key {
+            Some(wrapped_key) => wrapped_key.unwrap(kek),
+            None => Err(Error::InvalidArgument(
+                "Pointer is not encrypted with a key-encryption key".to_string(),
+            )),
+        }
+    }
+
+    /// Wraps the data key of the pointer with `new_kek` instead of `kek`.
+    /// Only the pointer header changes, the blocks are not re-encrypted.
+    pub fn rewrap(mut self, kek: &Key, new_kek: &Key) -> Result<Pointer, Error> {
+        let key = self.data_key(kek)?;
+        self.0.wrapped_key = Some(WrappedKey::wrap(new_kek, &key)?);
+        Ok(self)
+    }
+
+    /// Changes the passphrase of a pointer encrypted with
+    /// `encrypt_with_passphrase`, deriving the new key-encryption key with
+    /// `params`. See `rewrap`.
+    pub fn rekey(
+        mut self,
+        passphrase: &str,
+        new_passphrase: &str,
+        params: KdfParams,
+    ) -> Result<Pointer, Error> {
+        let kek = match &self.0.kdf {
+            Some(old_params) => old_params.derive_key(passphrase.as_bytes())?,
+            None => {
+                return Err(Error::Kdf(
+                    "Pointer has no key derivation parameters".to_string(),
+                ))
             }
         };
+        let new_kek = params.derive_key(new_passphrase.as_bytes())?;
+
+        self = self.rewrap(&kek, &new_kek)?;
+        self.0.kdf = Some(params);
+        Ok(self)
+    }
 
-        let mut metadata: Vec<u8> = vec![];
-        metadata.extend_from_slice(&ptext.as_slice());
+    /// Encrypts the pointer with a random data key, wrapped to each of the
+    /// `recipients` so that any of them can decrypt the pointer with their
+    /// `Identity`.
+    pub fn encrypt_for_recipients(self, recipients: &[Recipient]) -> Result<Pointer, Error> {
+        let key = random_key();
+        let stanzas = wrap_for_recipients(&key, recipients)?;
 
-        let mut dec_blocks: Vec<Block> = vec![];
+        let mut pointer = self.encrypt(&key)?;
+        pointer.0.recipients = stanzas;
+
+        Ok(pointer)
+    }
+
+    /// Decrypts a pointer encrypted with `encrypt_for_recipients`, unwrapping
+    /// the data key with `identity`.
+    pub fn decrypt_with_identity(self, identity: &Identity) -> Result<Pointer, Error> {
+        let key = self.unwrap_key(identity)?;
+        self.decrypt(&key)
+    }
 
-        // decrypt blocks
-        for block in self.0.blocks {
-            let dec_block = block.decrypt(key);
-            dec_blocks.push(dec_block);
+    /// Unwraps the data key of a pointer encrypted with
+    /// `encrypt_for_recipients`. Fails with `Error::WrongKey` if the pointer
+    /// was not encrypted to `identity`.
+    pub fn unwrap_key(&self, identity: &Identity) -> Result<Key, Error> {
+        if self.0.recipients.is_empty() {
+            return Err(Error::InvalidArgument(
+                "Pointer is not encrypted to recipients".to_string(),
+            ));
         }
+        identity.unwrap_key(&self.0.recipients)
+    }
 
-        let wrapper = Wrapper {
-            cid,
-            metadata: metadata.into_boxed_slice(),
-            head_block: self.0.head_block,
-            blocks: dec_blocks,
+    /// Persists the pointer in a backend. Each block is stored as a separate
+    /// object addressed by the CID of its serialised form, and the pointer is
+    /// stored with links to them. Returns the root CID of the stored pointer.
+    pub fn store(&mut self, backend: &mut dyn Backend) -> Result<String, Error> {
+        // a pointer built with a `PointerBuilder` has its blocks stored already
+        if !self.0.blocks.is_empty() {
+            let mut links = vec![];
+            for block in &self.0.blocks {
+                links.push(store_block(block, backend)?);
+            }
+            self.0.links = links;
+        }
+
+        // blocks are stored separately and are not part of the stored pointer
+        let blocks = std::mem::take(&mut self.0.blocks);
+        let raw = serde_cbor::to_vec(&self.0).map_err(|e| Error::Encode(e.to_string()));
+        self.0.blocks = blocks;
+        let raw = raw?;
+
+        let cid = cid_of(&raw)?;
+        backend.put(&cid, &raw)?;
+
+        Ok(cid)
+    }
+
+    /// Loads a pointer and all its blocks from a backend, given the root CID
+    /// returned by `store`.
+    pub fn load(cid: &str, backend: &dyn Backend) -> Result<Pointer, Error> {
+        let mut pointer = Pointer::load_header(cid, backend)?;
+
+        let mut blocks = vec![];
+        for link in &pointer.0.links {
+            blocks.push(fetch_block(link, backend)?);
+        }
+        pointer.0.blocks = blocks;
+
+        Ok(pointer)
+    }
+
+    /// Loads a pointer from a backend without fetching its blocks. The blocks
+    /// can then be read lazily with a `PointerReader`.
+    pub fn load_header(cid: &str, backend: &dyn Backend) -> Result<Pointer, Error> {
+        let raw = get_object(cid, backend)?;
+        let wrapper: Wrapper = serde_cbor::from_slice(&raw)
+            .map_err(|e| Error::Decode(format!("Error decoding pointer {}: {}", cid, e)))?;
+
+        Ok(Pointer(wrapper))
+    }
+
+    /// Loads the format version of an object of a backend if it is a pointer
+    /// of any format, or `None` if it is not, such as a block. Pointers which
+    /// predate `FORMAT_VERSION` may not load with `load_header`, and have
+    /// version 0 if they record none.
+    pub fn load_version(cid: &str, backend: &dyn Backend) -> Result<Option<u32>, Error> {
+        let raw = get_object(cid, backend)?;
+        let fields = match serde_cbor::from_slice(&raw) {
+            Ok(serde_cbor::Value::Map(fields)) => fields,
+            _ => return Ok(None),
         };
 
-        Ok(Pointer { 0: wrapper })
+        // pointers of every format have a head block field, and blocks do not
+        let field = |name: &str| fields.get(&serde_cbor::Value::Text(name.to_string()));
+        if field("head_block").is_none() {
+            return Ok(None);
+        }
+        let error = || Error::Decode(format!("Pointer {} has an invalid format version", cid));
+        match field("version") {
+            None => Ok(Some(0)),
+            Some(serde_cbor::Value::Integer(version)) => {
+                u32::try_from(*version).map(Some).map_err(|_| error())
+            }
+            Some(_) => Err(error()),
+        }
+    }
+
+    /// Returns a streaming reader over the plaintext of an encrypted pointer,
+    /// which fetches and decrypts one block at a time from the backend.
+    pub fn reader<'a>(
+        self,
+        key: &Key,
+        backend: &'a dyn Backend,
+    ) -> Result<PointerReader<'a>, Error> {
+        PointerReader::new(self, key, backend)
+    }
+
+    pub fn chunker(&self) -> &Chunker {
+        &self.0.chunker
+    }
+
+    pub fn kdf_params(&self) -> Option<&KdfParams> {
+        self.0.kdf.as_ref()
+    }
+
+    /// Metadata of the file the pointer refers to. The metadata of an
+    /// encrypted pointer is only known once it is decrypted
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.0.metadata.as_ref()
+    }
+
+    /// Makes the pointer searchable: stores the blind index tokens of its
+    /// metadata tags under the tag tokens key derived from the search key, see
+    /// `tag_tokens_key`, and its membership token, see `member_token`. The
+    /// tokens are kept in clear once the pointer is encrypted.
+    pub fn searchable(mut self, tokens_key: &Key) -> Result<Self, Error> {
+        self.0.tag_tokens = search_tokens(self.0.metadata.as_ref(), tokens_key)?;
+        Ok(self)
     }
 
-    pub fn metadata(&self) -> &[u8] {
-        &self.0.metadata
+    /// Whether the pointer was made searchable under `tokens_key`, even if it
+    /// has no tags.
+    pub fn is_searchable(&self, tokens_key: &Key) -> bool {
+        self.0.tag_tokens.contains(&member_token(tokens_key))
     }
 
+    /// Blind index tokens of a searchable pointer: its membership token and
+    /// the tokens of its tags
+    pub fn tag_tokens(&self) -> &[String] {
+        &self.0.tag_tokens
+    }
+
+    /// Attaches `metadata` to a plaintext pointer, setting its size to the
+    /// size of the pointer data. The metadata is encrypted together with the
+    /// pointer.
+    pub fn with_metadata(mut self, mut metadata: Metadata) -> Self {
+        metadata.size = self.size();
+        self.0.metadata = Some(metadata);
+        self
+    }
+
+    /// Sets the cipher the pointer is encrypted with. Defaults to AES-256-GCM.
+    pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+        self.0.cipher = cipher;
+        self
+    }
+
+    /// Cipher the pointer is encrypted with
+    pub fn cipher(&self) -> Cipher {
+        self.0.cipher
+    }
+
+    /// CID of the plaintext, or of the encrypted manifest of an encrypted
+    /// pointer
     pub fn cid(&self) -> &str {
         &self.0.cid
     }
 
+    /// CIDs of the stored blocks of a pointer loaded from a backend
+    pub fn links(&self) -> &[String] {
+        &self.0.links
+    }
+
     pub fn blocks_len(&self) -> usize {
-        return self.0.blocks.len();
+        if self.0.blocks.is_empty() {
+            return self.0.links.len();
+        }
+        self.0.blocks.len()
+    }
+
+    /// Size in bytes of the plaintext the pointer refers to. The size of an
+    /// encrypted pointer is only known once it is decrypted
+    pub fn size(&self) -> u64 {
+        self.0.lengths.iter().sum()
+    }
+}
+
+/// Returns the tokens of a pointer with `metadata` made searchable under
+/// `tokens_key`: its membership token, then the tokens of its tags.
+fn search_tokens(metadata: Option<&Metadata>, tokens_key: &Key) -> Result<Vec<String>, Error> {
+    let mut tokens = vec![member_token(tokens_key)];
+    if let Some(metadata) = metadata {
+        tokens.extend(metadata.tag_tokens(tokens_key)?);
     }
+    Ok(tokens)
+}
+
+/// Wraps the data key of a pointer to each of its recipients.
+fn wrap_for_recipients(key: &Key, recipients: &[Recipient]) -> Result<Vec<Stanza>, Error> {
+    if recipients.is_empty() {
+        return Err(Error::InvalidArgument(
+            "At least one recipient is required".to_string(),
+        ));
+    }
+    recipients.iter().map(|r| r.wrap_key(key)).collect()
+}
+
+/// Stores a block in a backend, returning the CID it is addressed by.
+fn store_block(block: &Block, backend: &mut dyn Backend) -> Result<String, Error> {
+    let raw = serde_cbor::to_vec(block).map_err(|e| Error::Encode(e.to_string()))?;
+    let cid = cid_of(&raw)?;
+    backend.put(&cid, &raw)?;
+
+    Ok(cid)
+}
+
+/// Fetches a stored block from a backend.
+fn fetch_block(cid: &str, backend: &dyn Backend) -> Result<Block, Error> {
+    let raw = get_object(cid, backend)?;
+    serde_cbor::from_slice(&raw)
+        .map_err(|e| Error::CorruptBlock(format!("Error decoding block {}: {}", cid, e)))
+}
+
+/// Fetches an object from a backend and checks that its content matches the
+/// CID it is addressed by.
+fn get_object(cid: &str, backend: &dyn Backend) -> Result<Vec<u8>, Error> {
+    let raw = match backend.get(cid)? {
+        Some(raw) => raw,
+        None => return Err(Error::MissingBlock(cid.to_string())),
+    };
+
+    if cid_of(&raw)? != cid {
+        return Err(Error::CorruptBlock(format!(
+            "Object {} does not match its CID",
+            cid
+        )));
+    }
+
+    Ok(raw)
 }
 
 #[cfg(test)]
@@ -284,23 +994,61 @@ mod tests {
 
     #[test]
     fn pointer_constructor() {
-        let synthetic_data = [1_u8; MAX_BLOCK_SIZE + 1];
+        let synthetic_data = [1_u8; LEGACY_BLOCK_SIZE + 1];
         let expected_ptr_cid =
             "baejbeidf3xehfzoocgwqaddxr64ggxzuh5yucgzpzhgv772z4ws552kui4".to_string();
 
-        let p = Pointer::from(&synthetic_data).unwrap();
+        let p = Pointer::from_with_block_size(&synthetic_data, LEGACY_BLOCK_SIZE).unwrap();
         assert_eq!(p.cid(), expected_ptr_cid);
         assert_eq!(p.blocks_len(), 2);
     }
 
+    #[test]
+    fn pointer_block_size() {
+        let synthetic_data = [1_u8; 1000];
+
+        let p = Pointer::from_with_block_size(&synthetic_data, 100).unwrap();
+        assert_eq!(p.blocks_len(), 10);
+
+        assert!(Pointer::from_with_block_size(&synthetic_data, 0).is_err());
+        assert!(Pointer::from_with_block_size(&synthetic_data, MAX_BLOCK_SIZE + 1).is_err());
+
+        // a block takes at most the largest block size of its chunker
+        let chunker = Chunker::Fixed { size: 100 };
+        let mut block = Block::for_chunker(&chunker);
+        assert!(block.write_all(&synthetic_data[..100]).is_ok());
+        assert!(block.write_all(&synthetic_data[..101]).is_err());
+        let mut block = Block::for_chunker(&Chunker::fast_cdc(128));
+        assert!(block.write_all(&synthetic_data[..512]).is_ok());
+        assert!(block.write_all(&synthetic_data[..513]).is_err());
+        assert!(Block::new_empty()
+            .write_all(&vec![0; MAX_BLOCK_SIZE + 1])
+            .is_err());
+    }
+
+    #[test]
+    fn pointer_default_block_size() {
+        let synthetic_data = vec![1_u8; DEFAULT_BLOCK_SIZE * 2 + 1];
+        let p = Pointer::from(&synthetic_data).unwrap();
+        assert_eq!(p.blocks_len(), 3);
+        assert_eq!(
+            p.chunker(),
+            &Chunker::Fixed {
+                size: DEFAULT_BLOCK_SIZE
+            }
+        );
+
+        assert!(Pointer::from_with_block_size(&synthetic_data, MIN_BLOCK_SIZE - 1).is_err());
+    }
+
     #[test]
     fn pointer_read() {
-        let synthetic_data = [1_u8; MAX_BLOCK_SIZE + 1];
+        let synthetic_data = [1_u8; LEGACY_BLOCK_SIZE + 1];
         let expected_ptr_cid =
             "baejbeidf3xehfzoocgwqaddxr64ggxzuh5yucgzpzhgv772z4ws552kui4".to_string();
-        let expected_total_bytes = MAX_BLOCK_SIZE + 1;
+        let expected_total_bytes = LEGACY_BLOCK_SIZE + 1;
 
-        let mut p = Pointer::from(&synthetic_data).unwrap();
+        let mut p = Pointer::from_with_block_size(&synthetic_data, LEGACY_BLOCK_SIZE).unwrap();
         assert_eq!(p.cid(), expected_ptr_cid);
         assert_eq!(p.blocks_len(), 2);
 
@@ -311,12 +1059,95 @@ mod tests {
         assert_eq!(dst_vec.len(), expected_total_bytes);
     }
 
+    #[test]
+    fn content_defined_chunking() {
+        // deterministic pseudo-random data
+        let mut state: u64 = 7;
+        let data: Vec<u8> = (0..64 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+
+        // inserts one byte in the middle of the data
+        let mut edited = data.clone();
+        edited.insert(30_000, 0xff);
+
+        let block_cids =
+            |p: &Pointer| -> Vec<String> { p.0.blocks.iter().map(|b| b.cid.clone()).collect() };
+        let changed_blocks = |chunker: Chunker| -> usize {
+            let original = block_cids(&Pointer::from_with_chunker(&data, chunker).unwrap());
+            let edited = block_cids(&Pointer::from_with_chunker(&edited, chunker).unwrap());
+            edited.iter().filter(|cid| !original.contains(cid)).count()
+        };
+
+        // with fixed size blocks, every block after the edit changes
+        let fixed = Chunker::Fixed { size: 512 };
+        assert!(changed_blocks(fixed) > 60);
+
+        // with content-defined chunking, only the blocks around the edit change
+        let cdc = Chunker::fast_cdc(512);
+        assert!(changed_blocks(cdc) <= 2);
+
+        let p = Pointer::from_with_chunker(&data, cdc).unwrap();
+        assert_eq!(p.chunker(), &cdc);
+        let mut out = vec![];
+        p.clone().read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn pointer_read_seek() {
+        let synthetic_data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 3).map(|i| i as u8).collect();
+        let mut p = Pointer::from_with_block_size(&synthetic_data, LEGACY_BLOCK_SIZE).unwrap();
+        assert_eq!(p.size(), synthetic_data.len() as u64);
+
+        // reads in small steps, across block boundaries
+        let mut dst_vec: Vec<u8> = vec![];
+        let mut buf = [0; 100];
+        loop {
+            let n = p.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            dst_vec.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(dst_vec, synthetic_data);
+
+        let pos = p
+            .seek(SeekFrom::Start(LEGACY_BLOCK_SIZE as u64 - 2))
+            .unwrap();
+        assert_eq!(pos, LEGACY_BLOCK_SIZE as u64 - 2);
+        let mut buf = [0; 4];
+        p.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            synthetic_data[LEGACY_BLOCK_SIZE - 2..LEGACY_BLOCK_SIZE + 2]
+        );
+
+        p.seek(SeekFrom::End(-1)).unwrap();
+        let mut rest = vec![];
+        p.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, vec![*synthetic_data.last().unwrap()]);
+
+        assert!(p.seek(SeekFrom::Current(-100_000)).is_err());
+
+        // std::io::copy works over a pointer
+        p.seek(SeekFrom::Start(0)).unwrap();
+        let mut copied = vec![];
+        std::io::copy(&mut p, &mut copied).unwrap();
+        assert_eq!(copied, synthetic_data);
+    }
+
     #[test]
     fn serialization() {
         use serde_cbor::de;
 
-        let synthetic_data = [1_u8; MAX_BLOCK_SIZE + 1];
-        let p = Pointer::from(&synthetic_data).unwrap();
+        let synthetic_data = [1_u8; LEGACY_BLOCK_SIZE + 1];
+        let p = Pointer::from_with_block_size(&synthetic_data, LEGACY_BLOCK_SIZE).unwrap();
         let serial_p = serde_cbor::to_vec(&p).unwrap();
         let p_deser: Pointer = de::from_slice(&serial_p).unwrap();
 
@@ -369,14 +1200,39 @@ mod tests {
         assert_eq!(original_block.cid, expected_block_cid);
 
         // encrypts content
-        let key = b"an example very very secret key.";
-        let enc_b = original_block.clone().encrypt(key);
-        assert_eq!(enc_b.cid, expected_block_cid + "/encrypted");
+        let key = &Key::from(b"an example very very secret key.");
+        let enc_b = original_block.clone().encrypt(key, &[]).unwrap();
+        assert_eq!(enc_b.cid, cid_of(&enc_b.data).unwrap());
+        assert_ne!(enc_b.cid, expected_block_cid);
+        assert_eq!(enc_b.nonce.len(), crate::crypto::NONCE_SIZE_BYTES);
+
+        // encrypting the same block twice uses a fresh nonce every time
+        let other_enc_b = original_block.clone().encrypt(key, &[]).unwrap();
+        assert_ne!(enc_b.nonce, other_enc_b.nonce);
+        assert_ne!(enc_b.data, other_enc_b.data);
 
         // decrypts encrypted block
-        let dec_b = enc_b.clone().decrypt(key);
+        let dec_b = enc_b.clone().decrypt(key, &[]).unwrap();
         assert_eq!(original_block.cid, dec_b.cid);
         assert_eq!(original_block.data, dec_b.data);
+
+        // a wrong key or tampered ciphertext fails authentication
+        let wrong_key = &Key::from(b"another very very secret key....");
+        assert!(matches!(
+            enc_b.clone().decrypt(wrong_key, &[]),
+            Err(Error::Authentication(_))
+        ));
+        let mut tampered = enc_b;
+        tampered.data[0] ^= 1;
+        assert!(matches!(
+            tampered.clone().decrypt(key, &[]),
+            Err(Error::IntegrityMismatch(_))
+        ));
+        tampered.cid = cid_of(&tampered.data).unwrap();
+        assert!(matches!(
+            tampered.decrypt(key, &[]),
+            Err(Error::Authentication(_))
+        ));
     }
 
     #[test]
@@ -386,12 +1242,12 @@ mod tests {
         let file_buffer = std::fs::read(file!()).unwrap();
 
         // creates pointer for file
-        let pointer = Pointer::from(&file_buffer).unwrap();
+        let pointer = Pointer::from_with_block_size(&file_buffer, LEGACY_BLOCK_SIZE).unwrap();
 
         assert!(pointer.blocks_len() > 1);
 
         // encrypts pointer
-        let key = b"hello darkness my good ol friend";
+        let key = &Key::from(b"hello darkness my good ol friend");
         let encrypted_pointer = pointer.clone().encrypt(key).unwrap();
 
         assert_eq!(pointer.blocks_len(), encrypted_pointer.blocks_len());
@@ -415,4 +1271,814 @@ mod tests {
         assert_eq!(final_buffer.len(), file_buffer.len());
         assert_eq!(final_buffer, file_buffer);
     }
+
+    #[test]
+    fn store_load() {
+        use crate::backend::MemoryBackend;
+
+        let file_buffer = std::fs::read(file!()).unwrap();
+        let pointer = Pointer::from_with_block_size(&file_buffer, LEGACY_BLOCK_SIZE).unwrap();
+
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let mut encrypted_pointer = pointer.encrypt(key).unwrap();
+
+        let mut backend = MemoryBackend::new();
+        let root = encrypted_pointer.store(&mut backend).unwrap();
+
+        // one object per block plus the pointer itself
+        assert_eq!(backend.len(), encrypted_pointer.blocks_len() + 1);
+        assert_eq!(root, cid_of(&backend.get(&root).unwrap().unwrap()).unwrap());
+
+        let loaded_pointer = Pointer::load(&root, &backend).unwrap();
+        assert_eq!(loaded_pointer.cid(), encrypted_pointer.cid());
+        assert_eq!(loaded_pointer.blocks_len(), encrypted_pointer.blocks_len());
+
+        let mut decrypted_pointer = loaded_pointer.decrypt(key).unwrap();
+        let mut final_buffer: Vec<u8> = vec![];
+        decrypted_pointer.read_to_end(&mut final_buffer);
+        assert_eq!(final_buffer, file_buffer);
+
+        // a wrong key is reported as such
+        let loaded_pointer = Pointer::load(&root, &backend).unwrap();
+        assert!(matches!(
+            loaded_pointer.decrypt(&Key::from(b"another very very secret key....")),
+            Err(Error::WrongKey)
+        ));
+
+        // a missing block fails to load
+        let link = encrypted_pointer.0.links[1].clone();
+        backend.delete(&link).unwrap();
+        match Pointer::load(&root, &backend) {
+            Err(Error::MissingBlock(cid)) => assert_eq!(cid, link),
+            r => panic!("unexpected result {:?}", r.map(|p| p.cid().to_string())),
+        }
+
+        // a tampered object fails to load
+        backend.put(&root, b"tampered").unwrap();
+        assert!(matches!(
+            Pointer::load(&root, &backend),
+            Err(Error::CorruptBlock(_))
+        ));
+    }
+
+    #[test]
+    fn delete() {
+        use crate::backend::MemoryBackend;
+
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let pointer = Pointer::from_with_block_size(&data, LEGACY_BLOCK_SIZE).unwrap();
+
+        let mut backend = MemoryBackend::new();
+        let root = pointer
+            .clone()
+            .encrypt(key)
+            .unwrap()
+            .store(&mut backend)
+            .unwrap();
+        let other = Pointer::from(&[1, 2, 3])
+            .unwrap()
+            .encrypt(key)
+            .unwrap()
+            .store(&mut backend)
+            .unwrap();
+
+        // only the key holder can delete a pointer
+        assert!(matches!(
+            Pointer::delete(
+                &root,
+                &Key::from(b"another very very secret key...."),
+                &mut backend
+            ),
+            Err(Error::WrongKey)
+        ));
+        Pointer::delete(&root, key, &mut backend).unwrap();
+        assert_eq!(backend.list().unwrap().len(), 2);
+        assert!(backend.has(&other).unwrap());
+
+        // blocks of convergent pointers may be shared, so they are kept
+        let mut p1 = pointer.clone().encrypt_convergent(key).unwrap();
+        let root = p1.store(&mut backend).unwrap();
+        let root2 = pointer
+            .encrypt_convergent(key)
+            .unwrap()
+            .store(&mut backend)
+            .unwrap();
+        Pointer::delete(&root, key, &mut backend).unwrap();
+        assert!(!backend.has(&root).unwrap());
+        let mut out = vec![];
+        Pointer::load_header(&root2, &backend)
+            .unwrap()
+            .reader(key, &backend)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn pointer_integrity() {
+        let synthetic_data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 2)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let pointer = Pointer::from_with_block_size(&synthetic_data, LEGACY_BLOCK_SIZE).unwrap();
+
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let mut encrypted_pointer = pointer.encrypt(key).unwrap();
+
+        // the manifest does not match the pointer CID
+        let cid = cid_of(b"another file").unwrap();
+        encrypted_pointer.0.cid = cid.clone();
+        match encrypted_pointer.decrypt(key) {
+            Err(Error::IntegrityMismatch(c)) => assert_eq!(c, cid),
+            r => panic!("unexpected result {:?}", r.map(|p| p.cid().to_string())),
+        }
+    }
+
+    fn encrypted_pointer(data: &[u8], key: &Key) -> Pointer {
+        Pointer::from_with_block_size(data, LEGACY_BLOCK_SIZE)
+            .unwrap()
+            .encrypt(key)
+            .unwrap()
+    }
+
+    #[test]
+    fn block_reordering() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut pointer = encrypted_pointer(&data, key);
+
+        pointer.0.blocks.swap(0, 1);
+        assert!(matches!(
+            pointer.decrypt(key),
+            Err(Error::Authentication(_))
+        ));
+    }
+
+    #[test]
+    fn block_truncation() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let pointer = encrypted_pointer(&data, key);
+
+        // the manifest is bound to the number of blocks, so blocks cannot be
+        // dropped from the pointer without it failing authentication
+        let mut truncated = pointer.clone();
+        truncated.0.blocks.pop();
+        assert!(matches!(
+            truncated.decrypt(key),
+            Err(Error::Authentication(_))
+        ));
+
+        let mut truncated = pointer.clone();
+        truncated.0.blocks.clear();
+        assert!(matches!(
+            truncated.decrypt(key),
+            Err(Error::Authentication(_))
+        ));
+
+        // nor with a manifest sealed again for the remaining blocks, since the
+        // new last block was not encrypted as the last one
+        let mut truncated = pointer.clone();
+        let mut manifest = truncated.open_manifest(key).unwrap();
+        truncated.0.blocks.pop();
+        manifest.block_cids.pop();
+        manifest.lengths.pop();
+        manifest.cid = root_cid(&manifest.block_cids).unwrap();
+        truncated.seal_manifest(&manifest, key).unwrap();
+        assert!(matches!(
+            truncated.decrypt(key),
+            Err(Error::Authentication(_))
+        ));
+
+        // a block which is not the last one cannot be read as the last one
+        let block = pointer.0.blocks[1].clone();
+        let blocks_key = &blocks_key(key);
+        let ad = block_ad(&pointer.0.stream_id, 1, true);
+        assert!(matches!(
+            block.clone().decrypt(blocks_key, &ad),
+            Err(Error::Authentication(_))
+        ));
+        let ad = block_ad(&pointer.0.stream_id, 1, false);
+        assert!(block.decrypt(blocks_key, &ad).is_ok());
+    }
+
+    #[test]
+    fn block_splicing() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut pointer = encrypted_pointer(&data, key);
+
+        // a validly encrypted block of another pointer, under the same key
+        // and at the same position, holding the same data
+        let other = encrypted_pointer(&data, key);
+        assert_ne!(pointer.0.stream_id, other.0.stream_id);
+        pointer.0.blocks[1] = other.0.blocks[1].clone();
+
+        assert!(matches!(
+            pointer.decrypt(key),
+            Err(Error::Authentication(_))
+        ));
+    }
+
+    #[test]
+    fn mismatched_key_commitment() {
+        use crate::backend::MemoryBackend;
+
+        // A malicious sender shares a pointer with Alice and Bob, wrapping a
+        // different data key to each, so that they read different files (see
+        // `invisible_salamander_pointer` for such a ciphertext). The
+        // pointer here is encrypted with Bob's key, but commits to Alice's:
+        // its header is rejected under Bob's key before anything is decrypted.
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let alice_key = random_key();
+        let bob_key = random_key();
+
+        let mut crafted = Pointer::from(b"a file only bob should see")
+            .unwrap()
+            .encrypt(&bob_key)
+            .unwrap();
+        assert!(crafted.clone().decrypt(&bob_key).is_ok());
+        crafted.0.key_commitment = key_commitment(&alice_key, &crafted.0.stream_id);
+        crafted.0.recipients = vec![
+            alice.recipient().wrap_key(&alice_key).unwrap(),
+            bob.recipient().wrap_key(&bob_key).unwrap(),
+        ];
+
+        // Bob unwraps a key the pointer does not commit to, so nothing is
+        // decrypted with it even though the ciphertext is valid under it
+        assert_eq!(crafted.unwrap_key(&bob).unwrap(), bob_key);
+        assert!(matches!(
+            crafted.clone().decrypt_with_identity(&bob),
+            Err(Error::WrongKey)
+        ));
+        assert!(matches!(
+            crafted.clone().decrypt_header(&bob_key),
+            Err(Error::WrongKey)
+        ));
+
+        let mut backend = MemoryBackend::new();
+        let root = crafted.clone().store(&mut backend).unwrap();
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        assert!(matches!(
+            header.reader(&bob_key, &backend),
+            Err(Error::WrongKey)
+        ));
+        assert!(matches!(
+            Pointer::delete(&root, &bob_key, &mut backend),
+            Err(Error::WrongKey)
+        ));
+
+        // nor can the commitment be left out
+        crafted.0.key_commitment = vec![];
+        assert!(matches!(
+            crafted.decrypt_with_identity(&bob),
+            Err(Error::WrongKey)
+        ));
+    }
+
+    #[test]
+    fn invisible_salamander_pointer() {
+        use crate::backend::MemoryBackend;
+        use crate::crypto::tests::salamander;
+
+        // The block of this pointer authenticates under two data keys, and
+        // decrypts to different data under each. The pointer commits to the
+        // first key, so it is rejected under the second before any block is
+        // opened.
+        let k1 = &Key::from(b"hello darkness my good ol friend");
+        let k2 = &Key::from(b"another very very secret key....");
+        let mut pointer = Pointer::from(&[0; 32]).unwrap().encrypt(k1).unwrap();
+        let ad = block_ad(&pointer.0.stream_id, 0, true);
+        let block = &mut pointer.0.blocks[0];
+        let ctext = salamander(&blocks_key(k1), &blocks_key(k2), &block.nonce, &ad);
+        block.cid = cid_of(&ctext).unwrap();
+        block.data = ctext.into_boxed_slice();
+
+        let b1 = block.clone().decrypt(&blocks_key(k1), &ad).unwrap();
+        let b2 = block.clone().decrypt(&blocks_key(k2), &ad).unwrap();
+        assert_ne!(b1.data, b2.data);
+
+        // the manifest describes the block as decrypted with the first key
+        let block_cids = vec![b1.cid.clone()];
+        let manifest = Manifest {
+            cid: root_cid(&block_cids).unwrap(),
+            head_block: Some(b1.cid.clone()),
+            block_cids,
+            lengths: vec![b1.data.len() as u64],
+            block_keys: vec![],
+            metadata: None,
+        };
+        pointer.seal_manifest(&manifest, k1).unwrap();
+        let mut out = vec![];
+        pointer
+            .clone()
+            .decrypt(k1)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, b1.data.to_vec());
+        assert!(matches!(pointer.clone().decrypt(k2), Err(Error::WrongKey)));
+
+        // stored without its block, the pointer is rejected under the second
+        // key, while under the first the missing block is fetched
+        let mut backend = MemoryBackend::new();
+        let root = pointer.store(&mut backend).unwrap();
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        backend.delete(&header.links()[0]).unwrap();
+        assert!(matches!(
+            header.clone().reader(k2, &backend),
+            Err(Error::WrongKey)
+        ));
+        let link = header.links()[0].clone();
+        let mut reader = header.reader(k1, &backend).unwrap();
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+        assert!(matches!(Error::from(err), Error::MissingBlock(cid) if cid == link));
+    }
+
+    #[test]
+    fn unique_nonces() {
+        let synthetic_data = [1_u8; LEGACY_BLOCK_SIZE * 3];
+        let pointer = Pointer::from_with_block_size(&synthetic_data, LEGACY_BLOCK_SIZE).unwrap();
+
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let encrypted_pointer = pointer.encrypt(key).unwrap();
+
+        // all blocks have the same content, but none of them share a nonce
+        let mut nonces: Vec<&Vec<u8>> = encrypted_pointer
+            .0
+            .blocks
+            .iter()
+            .map(|b| &b.nonce)
+            .collect();
+        nonces.push(&encrypted_pointer.0.manifest_nonce);
+        nonces.sort();
+        nonces.dedup();
+        assert_eq!(nonces.len(), 4);
+
+        let blocks = &encrypted_pointer.0.blocks;
+        assert_ne!(blocks[0].data, blocks[1].data);
+    }
+
+    #[test]
+    fn pointer_metadata() {
+        use serde_cbor::Value;
+
+        let data = [1_u8; LEGACY_BLOCK_SIZE + 1];
+        let mut metadata = Metadata::new(Tags {
+            values: vec!["invoice".to_string()],
+        })
+        .unwrap();
+        metadata.filename = Some("invoice.pdf".to_string());
+        let pointer = Pointer::from_with_block_size(&data, LEGACY_BLOCK_SIZE)
+            .unwrap()
+            .with_metadata(metadata);
+        assert_eq!(pointer.metadata().unwrap().size, data.len() as u64);
+
+        // the metadata is only readable once the pointer is decrypted
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let encrypted_pointer = pointer.clone().encrypt(key).unwrap();
+        assert!(encrypted_pointer.metadata().is_none());
+        let raw = serde_cbor::to_vec(&encrypted_pointer).unwrap();
+        assert!(!raw.windows(11).any(|w| w == b"invoice.pdf"));
+
+        assert!(encrypted_pointer.is_encrypted());
+        assert!(!pointer.is_encrypted());
+
+        let header = encrypted_pointer.clone().decrypt_header(key).unwrap();
+        assert_eq!(header.metadata(), pointer.metadata());
+        let decrypted_pointer = encrypted_pointer.decrypt(key).unwrap();
+        assert_eq!(decrypted_pointer.metadata(), pointer.metadata());
+
+        // pointers stored before metadata was populated hold placeholder bytes
+        let mut value = serde_cbor::value::to_value(&pointer).unwrap();
+        if let Value::Map(fields) = &mut value {
+            fields.insert(Value::Text("metadata".to_string()), Value::Bytes(vec![0]));
+        }
+        let legacy_raw = serde_cbor::to_vec(&value).unwrap();
+        let legacy_pointer: Pointer = serde_cbor::from_slice(&legacy_raw).unwrap();
+        assert!(legacy_pointer.metadata().is_none());
+    }
+
+    #[test]
+    fn searchable_pointer() {
+        use crate::crypto::tag_token;
+
+        let metadata = Metadata::new(Tags {
+            values: vec!["invoice".to_string(), "2026".to_string()],
+        })
+        .unwrap();
+        let search_key = &Key::from(b"a search key, not the data key..");
+        let pointer = Pointer::from(&[1, 2, 3])
+            .unwrap()
+            .with_metadata(metadata)
+            .searchable(search_key)
+            .unwrap();
+
+        // the tokens can be matched on the stored header, without any key
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let mut encrypted_pointer = pointer.encrypt(key).unwrap();
+        let mut backend = crate::backend::MemoryBackend::new();
+        let root = encrypted_pointer.store(&mut backend).unwrap();
+        let header = Pointer::load_header(&root, &backend).unwrap();
+
+        assert!(header.query(tag_token(search_key, "invoice")).unwrap());
+        assert!(header.query(tag_token(search_key, "2026")).unwrap());
+        assert!(!header.query(tag_token(search_key, "receipt")).unwrap());
+        assert!(!header.query(tag_token(key, "invoice")).unwrap());
+        assert!(!header.query("invoice".to_string()).unwrap());
+
+        let raw = backend.get(&root).unwrap().unwrap();
+        assert!(!raw.windows(7).any(|w| w == b"invoice"));
+
+        // pointers are not searchable unless opted into
+        let pointer = Pointer::from(&[1, 2, 3]).unwrap().encrypt(key).unwrap();
+        assert!(!pointer.query(tag_token(search_key, "invoice")).unwrap());
+    }
+
+    #[test]
+    fn key_separation() {
+        use crate::crypto::tag_tokens_key;
+
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let metadata = Metadata::new(Tags {
+            values: vec!["invoice".to_string()],
+        })
+        .unwrap();
+        let pointer = Pointer::from(&[1, 2, 3])
+            .unwrap()
+            .with_metadata(metadata)
+            .searchable(&tag_tokens_key(key))
+            .unwrap()
+            .encrypt(key)
+            .unwrap();
+
+        // blocks and manifest are each encrypted with their own key
+        let ad = block_ad(&pointer.0.stream_id, 0, true);
+        let block = pointer.0.blocks[0].clone();
+        assert!(block.clone().decrypt(&metadata_key(key), &ad).is_err());
+        assert!(block.clone().decrypt(key, &ad).is_err());
+        assert!(block.decrypt(&blocks_key(key), &ad).is_ok());
+        assert!(matches!(
+            Manifest::decrypt(
+                pointer.0.cipher,
+                &pointer.0.manifest,
+                &pointer.0.manifest_nonce,
+                &blocks_key(key),
+                &pointer.0.stream_id,
+                1,
+            ),
+            Err(Error::Authentication(_))
+        ));
+
+        // even when derived from the data key, the tag tokens key matches the
+        // pointer tags but does not decrypt it
+        assert!(pointer
+            .query(crate::crypto::tag_token(&tag_tokens_key(key), "invoice"))
+            .unwrap());
+        assert!(matches!(
+            pointer.decrypt(&tag_tokens_key(key)),
+            Err(Error::WrongKey)
+        ));
+    }
+
+    #[test]
+    fn no_plaintext_cids() {
+        use crate::backend::MemoryBackend;
+
+        let data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let pointer = Pointer::from_with_block_size(&data, LEGACY_BLOCK_SIZE).unwrap();
+        let mut plaintext_cids: Vec<String> =
+            pointer.0.blocks.iter().map(|b| b.cid.clone()).collect();
+        plaintext_cids.push(pointer.cid().to_string());
+
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let mut encrypted_pointer = pointer.clone().encrypt(key).unwrap();
+        let mut backend = MemoryBackend::new();
+        encrypted_pointer.store(&mut backend).unwrap();
+
+        // neither the stored objects nor their CIDs reveal the plaintext CIDs
+        for cid in backend.list().unwrap() {
+            let raw = backend.get(&cid).unwrap().unwrap();
+            for plaintext_cid in &plaintext_cids {
+                assert_ne!(&cid, plaintext_cid);
+                assert!(!raw
+                    .windows(plaintext_cid.len())
+                    .any(|w| w == plaintext_cid.as_bytes()));
+            }
+        }
+
+        // encrypting the same pointer twice results in different CIDs
+        let other = pointer.encrypt(key).unwrap();
+        assert_ne!(other.cid(), encrypted_pointer.cid());
+        assert_ne!(other.0.blocks[0].cid, encrypted_pointer.0.blocks[0].cid);
+    }
+
+    #[test]
+    fn convergent_encryption() {
+        use crate::backend::MemoryBackend;
+
+        let data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let pointer = Pointer::from_with_block_size(&data, LEGACY_BLOCK_SIZE).unwrap();
+
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let other_key = &Key::from(b"another very very secret key....");
+        let mut p1 = pointer.clone().encrypt_convergent(key).unwrap();
+        let mut p2 = pointer.clone().encrypt_convergent(other_key).unwrap();
+
+        // identical blocks are stored once, even across keys
+        let mut backend = MemoryBackend::new();
+        p1.store(&mut backend).unwrap();
+        p2.store(&mut backend).unwrap();
+        assert_eq!(p1.0.links, p2.0.links);
+        assert_eq!(backend.len(), p1.blocks_len() + 2);
+
+        // but the pointers themselves do not share a CID
+        assert_ne!(p1.cid(), p2.cid());
+        assert!(p1.0.blocks.iter().all(|b| b.cid != pointer.0.blocks[0].cid));
+
+        let mut decrypted = p1.clone().decrypt(key).unwrap();
+        assert_eq!(decrypted.cid(), pointer.cid());
+        let mut out = vec![];
+        decrypted.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+        assert!(matches!(p1.decrypt(other_key), Err(Error::WrongKey)));
+
+        // the order of the blocks is bound to the manifest
+        p2.0.blocks.swap(0, 1);
+        assert!(matches!(
+            p2.decrypt(other_key),
+            Err(Error::Authentication(_))
+        ));
+    }
+
+    #[test]
+    fn passphrase_encrypt_decrypt() {
+        use serde_cbor::de;
+
+        let synthetic_data = [1_u8; LEGACY_BLOCK_SIZE + 1];
+        let pointer = Pointer::from_with_block_size(&synthetic_data, LEGACY_BLOCK_SIZE).unwrap();
+
+        let params = KdfParams::new(64, 1, 1);
+        let encrypted_pointer = pointer
+            .encrypt_with_passphrase("a human passphrase", params.clone())
+            .unwrap();
+        assert_eq!(encrypted_pointer.kdf_params(), Some(&params));
+
+        // kdf parameters survive serialisation
+        let serial_pointer = serde_cbor::to_vec(&encrypted_pointer).unwrap();
+        let current_pointer: Pointer = de::from_slice(&serial_pointer).unwrap();
+        assert_eq!(current_pointer.kdf_params(), Some(&params));
+
+        assert!(current_pointer
+            .clone()
+            .decrypt_with_passphrase("another passphrase")
+            .is_err());
+
+        let mut decrypted_pointer = current_pointer
+            .decrypt_with_passphrase("a human passphrase")
+            .unwrap();
+
+        let mut final_buffer: Vec<u8> = vec![];
+        decrypted_pointer.read_to_end(&mut final_buffer);
+        assert_eq!(final_buffer, synthetic_data.to_vec());
+    }
+
+    #[test]
+    fn ciphers() {
+        use crate::backend::MemoryBackend;
+
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data = [1_u8; LEGACY_BLOCK_SIZE + 1];
+
+        let mut ciphertexts = vec![];
+        for cipher in Cipher::ALL {
+            let pointer = Pointer::from_with_block_size(&data, LEGACY_BLOCK_SIZE)
+                .unwrap()
+                .with_cipher(cipher);
+            let encrypted = pointer.encrypt(key).unwrap();
+            assert_eq!(encrypted.cipher(), cipher);
+            for block in &encrypted.0.blocks {
+                assert_eq!(block.nonce.len(), cipher.nonce_size());
+            }
+            ciphertexts.push(encrypted.0.blocks[0].data.clone());
+
+            // the cipher is recorded in the stored header
+            let mut backend = MemoryBackend::new();
+            let root = encrypted.clone().store(&mut backend).unwrap();
+            let mut decrypted = Pointer::load(&root, &backend)
+                .unwrap()
+                .decrypt(key)
+                .unwrap();
+            assert_eq!(decrypted.cipher(), cipher);
+            let mut out = vec![];
+            decrypted.read_to_end(&mut out).unwrap();
+            assert_eq!(out, data.to_vec());
+
+            // a pointer does not decrypt with another cipher
+            let other = Cipher::ALL.iter().find(|c| **c != cipher).unwrap();
+            let mut swapped = encrypted;
+            swapped.0.cipher = *other;
+            assert!(swapped.decrypt(key).is_err());
+        }
+        ciphertexts.dedup();
+        assert_eq!(ciphertexts.len(), Cipher::ALL.len());
+    }
+
+    #[test]
+    fn rekey() {
+        use crate::backend::MemoryBackend;
+
+        let data = [1_u8; LEGACY_BLOCK_SIZE + 1];
+        let pointer = Pointer::from_with_block_size(&data, LEGACY_BLOCK_SIZE).unwrap();
+        let encrypted = pointer
+            .encrypt_with_passphrase("old passphrase", KdfParams::new(64, 1, 1))
+            .unwrap();
+
+        let mut backend = MemoryBackend::new();
+        let root = encrypted.clone().store(&mut backend).unwrap();
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        let key = header.derive_key("old passphrase").unwrap();
+
+        let params = KdfParams::new(64, 1, 1);
+        let mut rekeyed = header
+            .clone()
+            .rekey("old passphrase", "new passphrase", params.clone())
+            .unwrap();
+        assert_eq!(rekeyed.kdf_params(), Some(&params));
+        assert_ne!(rekeyed.kdf_params(), header.kdf_params());
+
+        // only the header changes, the blocks are the same
+        let new_root = rekeyed.store(&mut backend).unwrap();
+        assert_ne!(new_root, root);
+        let rekeyed = Pointer::load_header(&new_root, &backend).unwrap();
+        assert_eq!(rekeyed.0.links, header.0.links);
+        assert_eq!(rekeyed.cid(), header.cid());
+        assert_eq!(rekeyed.derive_key("new passphrase").unwrap(), key);
+        assert!(matches!(
+            rekeyed.derive_key("old passphrase"),
+            Err(Error::WrongKey)
+        ));
+
+        let mut decrypted = Pointer::load(&new_root, &backend)
+            .unwrap()
+            .decrypt_with_passphrase("new passphrase")
+            .unwrap();
+        let mut out = vec![];
+        decrypted.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data.to_vec());
+
+        assert!(matches!(
+            header
+                .clone()
+                .rekey("wrong passphrase", "new", params.clone()),
+            Err(Error::WrongKey)
+        ));
+
+        // pointers encrypted with the key-encryption key itself can not be
+        // rewrapped
+        let kek = params.derive_key(b"old passphrase").unwrap();
+        let mut unwrapped = Pointer::from(&data).unwrap().encrypt(&kek).unwrap();
+        unwrapped.0.kdf = Some(params.clone());
+        assert!(matches!(
+            unwrapped.derive_key("old passphrase"),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            unwrapped.rekey("old passphrase", "new passphrase", params),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn format_version() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let pointer = Pointer::from(b"hello world").unwrap();
+        assert_eq!(pointer.0.version, FORMAT_VERSION);
+        let encrypted = pointer
+            .encrypt_with_passphrase("passphrase", KdfParams::new(64, 1, 1))
+            .unwrap();
+        assert!(encrypted.is_supported());
+
+        // pointers stored without a version predate the current format, and
+        // are rejected before anything is decrypted
+        let mut value = serde_cbor::value::to_value(&encrypted).unwrap();
+        if let serde_cbor::Value::Map(fields) = &mut value {
+            fields.remove(&serde_cbor::Value::Text("version".to_string()));
+        }
+        let old: Pointer = serde_cbor::value::from_value(value).unwrap();
+        assert!(!old.is_supported());
+        assert!(matches!(
+            old.derive_key("passphrase"),
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(old.clone().decrypt(key), Err(Error::Decode(_))));
+        assert!(matches!(
+            old.rekey("passphrase", "new", KdfParams::new(64, 1, 1)),
+            Err(Error::Decode(_))
+        ));
+
+        // pointers of version 1 derive their keys differently, and are
+        // rejected rather than failing the key commitment
+        let mut old = encrypted;
+        old.0.version = 1;
+        assert!(!old.is_supported());
+        assert!(matches!(
+            old.derive_key("passphrase"),
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(old.decrypt(key), Err(Error::Decode(_))));
+    }
+
+    #[test]
+    fn load_version() {
+        use crate::backend::MemoryBackend;
+
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let mut backend = MemoryBackend::new();
+        let mut pointer = Pointer::from(&[1; 100]).unwrap().encrypt(key).unwrap();
+        let root = pointer.store(&mut backend).unwrap();
+        assert_eq!(
+            Pointer::load_version(&root, &backend).unwrap(),
+            Some(FORMAT_VERSION)
+        );
+        let block = &pointer.links()[0];
+        assert_eq!(Pointer::load_version(block, &backend).unwrap(), None);
+
+        // pointers stored before the format was versioned may lack fields
+        // since made required, and so do not load as a header
+        let mut value = serde_cbor::value::to_value(&pointer).unwrap();
+        if let serde_cbor::Value::Map(fields) = &mut value {
+            fields.remove(&serde_cbor::Value::Text("version".to_string()));
+            fields.remove(&serde_cbor::Value::Text("cipher".to_string()));
+        }
+        let raw = serde_cbor::to_vec(&value).unwrap();
+        let old = cid_of(&raw).unwrap();
+        backend.put(&old, &raw).unwrap();
+        assert!(matches!(
+            Pointer::load_header(&old, &backend),
+            Err(Error::Decode(_))
+        ));
+        assert_eq!(Pointer::load_version(&old, &backend).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn recipients_encrypt_decrypt() {
+        use crate::backend::MemoryBackend;
+
+        let data = [1_u8; LEGACY_BLOCK_SIZE + 1];
+        let pointer = Pointer::from_with_block_size(&data, LEGACY_BLOCK_SIZE).unwrap();
+
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let encrypted = pointer
+            .encrypt_for_recipients(&[alice.recipient(), bob.recipient()])
+            .unwrap();
+        assert_eq!(encrypted.0.recipients.len(), 2);
+        assert!(encrypted.kdf_params().is_none());
+
+        let mut backend = MemoryBackend::new();
+        let root = encrypted.clone().store(&mut backend).unwrap();
+        for identity in [&alice, &bob] {
+            let mut decrypted = Pointer::load(&root, &backend)
+                .unwrap()
+                .decrypt_with_identity(identity)
+                .unwrap();
+            let mut out = vec![];
+            decrypted.read_to_end(&mut out).unwrap();
+            assert_eq!(out, data.to_vec());
+        }
+
+        assert!(matches!(
+            encrypted.unwrap_key(&Identity::generate()),
+            Err(Error::WrongKey)
+        ));
+        assert!(Pointer::from(&data)
+            .unwrap()
+            .encrypt_for_recipients(&[])
+            .is_err());
+
+        // a pointer encrypted with a key has no recipients
+        let other = Pointer::from(&data)
+            .unwrap()
+            .encrypt(&Key::from(b"hello darkness my good ol friend"))
+            .unwrap();
+        assert!(matches!(
+            other.unwrap_key(&alice),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
 }
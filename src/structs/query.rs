@@ -0,0 +1,716 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+use super::value::{Value, TAGS_FIELD};
+use crate::crypto::{tag_token, Key};
+use crate::error::Error;
+
+/// Query is the parsed form of a search query, evaluated by `Match`
+/// implementations. See `Query::parse` for the syntax.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    /// Matches everything, the empty query
+    All,
+    Term(Term),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+}
+
+/// A single condition of a query.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    /// A plain tag, matched as a glob pattern where `*` matches any
+    /// characters and `?` a single one
+    Tag(String),
+    /// Compares the metadata field `key` to `value`, which is read according
+    /// to the type of the field, see `Value::compare`
+    Field { key: String, op: Cmp, value: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Query {
+    /// Parses a query. Terms are combined with `AND`, `OR` and `NOT`, and
+    /// grouped with parentheses. Terms next to each other are combined with
+    /// `AND`, which binds tighter than `OR`.
+    ///
+    /// - `invoice`, `inv*`, `20??`: tags, with glob patterns
+    /// - `author=al*`, `year>=2020`, `due<2026-01-01`: compares a metadata
+    ///   field, according to its type. `!=` is the negation of `=`
+    /// - `size>10MB`, `created<2026-01-01`, `filename=*.pdf`, `mime=image/*`:
+    ///   compares the file attributes. Sizes take KB, MB, GB (powers of 1000)
+    ///   or KiB, MiB, GiB (powers of 1024) units, and dates are either
+    ///   `YYYY-MM-DD`, `YYYY-MM-DDTHH:MM:SS` (UTC) or seconds since the epoch
+    ///
+    /// Double quotes match a tag with spaces or operators literally, such as
+    /// `"tax return"`.
+    pub fn parse(query: &str) -> Result<Query, Error> {
+        let tokens = tokenize(query)?;
+        if tokens.is_empty() {
+            return Ok(Query::All);
+        }
+
+        let mut parser = Parser { tokens, pos: 0 };
+        let query = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(query),
+            Some(token) => Err(invalid(&format!("unexpected {:?}", token))),
+        }
+    }
+
+    /// Evaluates the query, with `term` evaluating each of its terms.
+    pub fn eval<F>(&self, term: &F) -> Result<bool, Error>
+    where
+        F: Fn(&Term) -> Result<bool, Error>,
+    {
+        match self {
+            Query::All => Ok(true),
+            Query::Term(t) => term(t),
+            Query::And(a, b) => Ok(a.eval(term)? && b.eval(term)?),
+            Query::Or(a, b) => Ok(a.eval(term)? || b.eval(term)?),
+            Query::Not(q) => Ok(!q.eval(term)?),
+        }
+    }
+
+    /// Returns the query with each term replaced by `f`.
+    pub fn map_terms<F>(self, f: &F) -> Result<Query, Error>
+    where
+        F: Fn(Term) -> Result<Term, Error>,
+    {
+        Ok(match self {
+            Query::All => Query::All,
+            Query::Term(t) => Query::Term(f(t)?),
+            Query::And(a, b) => Query::And(Box::new(a.map_terms(f)?), Box::new(b.map_terms(f)?)),
+            Query::Or(a, b) => Query::Or(Box::new(a.map_terms(f)?), Box::new(b.map_terms(f)?)),
+            Query::Not(q) => Query::Not(Box::new(q.map_terms(f)?)),
+        })
+    }
+
+    /// Returns the query with its tags and `key=value` fields replaced by
+    /// their tag tokens, as computed by `Metadata::tag_tokens`, to be matched
+    /// against searchable pointers. Field values are read as `Tags::into_fields`
+    /// reads them, so that `due=2026-01-01` matches the timestamp it was stored
+    /// as. Only the tag tokens key is needed, see `tag_tokens_key`. Tag tokens
+    /// only reveal whether two tags are equal, so glob patterns and comparisons
+    /// are an error.
+    pub fn tag_tokens(self, tokens_key: &Key) -> Result<Query, Error> {
+        self.map_terms(&|term| match term {
+            Term::Tag(ref pattern) if is_literal(pattern) => {
+                Ok(Term::Tag(tag_token(tokens_key, &unescape_glob(pattern))))
+            }
+            Term::Field {
+                ref key,
+                op: Cmp::Eq,
+                ref value,
+            } if is_literal(value) && !is_attribute(key) => {
+                let value = unescape_glob(value);
+                let tag = match key.as_str() {
+                    TAGS_FIELD => value,
+                    _ => format!("{}={}", key, Value::parse(&value).token_text()[0]),
+                };
+                Ok(Term::Tag(tag_token(tokens_key, &tag)))
+            }
+            _ => Err(Error::InvalidArgument(
+                "Only exact tags and key=value tags can be searched with a search key".to_string(),
+            )),
+        })
+    }
+
+    pub fn and(self, other: Query) -> Query {
+        match self {
+            Query::All => other,
+            q => Query::And(Box::new(q), Box::new(other)),
+        }
+    }
+
+    pub fn or(self, other: Query) -> Query {
+        Query::Or(Box::new(self), Box::new(other))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Query {
+        Query::Not(Box::new(self))
+    }
+
+    /// A query matching the tag `tag` literally
+    pub fn tag(tag: &str) -> Query {
+        Query::Term(Term::Tag(escape_glob(tag)))
+    }
+}
+
+impl Cmp {
+    /// Whether `ordering`, of a value compared to the query value, satisfies
+    /// the comparison.
+    pub fn eval(self, ordering: Ordering) -> bool {
+        match self {
+            Cmp::Eq => ordering == Ordering::Equal,
+            Cmp::Ne => ordering != Ordering::Equal,
+            Cmp::Lt => ordering == Ordering::Less,
+            Cmp::Le => ordering != Ordering::Greater,
+            Cmp::Gt => ordering == Ordering::Greater,
+            Cmp::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl Term {
+    /// Returns the tag token of a query over tag tokens, which can only be
+    /// matched exactly.
+    pub fn token(&self) -> Result<&str, Error> {
+        match self {
+            Term::Tag(token) if is_literal(token) => Ok(token),
+            _ => Err(Error::InvalidArgument(
+                "Only exact tags can be matched against tag tokens".to_string(),
+            )),
+        }
+    }
+}
+
+/// Matches `text` against a glob pattern, where `*` matches any characters,
+/// `?` a single one and `\` escapes the next character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` in the pattern, and of the text it matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some('\\') if p + 1 < pattern.len() && pattern[p + 1] == text[t] => {
+                p += 2;
+                t += 1;
+                continue;
+            }
+            Some(c) if *c != '\\' && *c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match star {
+            // backtracks, letting the last `*` match one more character
+            Some((star_p, star_t)) => {
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Escapes the glob wildcards in `text`, so that it is matched literally.
+pub fn escape_glob(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Whether a glob pattern only matches the text it is made of.
+pub fn is_literal(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '?' => return false,
+            '\\' => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Returns the text a literal glob pattern matches.
+pub fn unescape_glob(pattern: &str) -> String {
+    let mut text = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    /// A term, as written in the query
+    Word(String),
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut chars = query.chars().peekable();
+
+    while let Some(c) = chars.peek().copied() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(c) = chars.peek().copied() {
+                    if !quoted && (c.is_whitespace() || c == '(' || c == ')') {
+                        break;
+                    }
+                    if c == '"' {
+                        quoted = !quoted;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                if quoted {
+                    return Err(invalid("unterminated quote"));
+                }
+
+                tokens.push(match word.as_str() {
+                    "AND" | "and" => Token::And,
+                    "OR" | "or" => Token::Or,
+                    "NOT" | "not" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query, Error> {
+        let mut query = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            query = query.or(self.and()?);
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, Error> {
+        let mut query = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                // terms next to each other
+                Some(Token::Not) | Some(Token::Open) | Some(Token::Word(_)) => {}
+                _ => return Ok(query),
+            }
+            query = Query::And(Box::new(query), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Query, Error> {
+        match self.next() {
+            Some(Token::Not) => Ok(self.unary()?.not()),
+            Some(Token::Open) => {
+                let query = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err(invalid("missing closing parenthesis")),
+                }
+            }
+            Some(Token::Word(word)) => parse_term(&word),
+            Some(token) => Err(invalid(&format!("unexpected {:?}", token))),
+            None => Err(invalid("unexpected end of query")),
+        }
+    }
+}
+
+/// Parses a single term, such as `invoice`, `author=alice` or `size>10MB`.
+fn parse_term(word: &str) -> Result<Query, Error> {
+    // a quoted term is a literal tag
+    if word.starts_with('"') {
+        return Ok(Query::tag(&unquote(word)));
+    }
+
+    let (key, op, value) = match split_comparison(word) {
+        Some(parts) => parts,
+        None => return Ok(Query::Term(Term::Tag(unquote(word)))),
+    };
+    // a quoted value is matched literally
+    let value = match value.starts_with('"') {
+        true => escape_glob(&unquote(value)),
+        false => unquote(value),
+    };
+    if key.is_empty() || value.is_empty() {
+        return Err(invalid(&format!("incomplete comparison {:?}", word)));
+    }
+
+    let key = match key {
+        "name" => "filename",
+        "mime" | "type" => "mime_type",
+        key => key,
+    };
+    // the file attributes are checked upfront, since they have a known type
+    match key {
+        "size" => {
+            parse_size(&value)?;
+        }
+        "created" => {
+            parse_date(&value)?;
+        }
+        _ => {}
+    }
+
+    let field = Query::Term(Term::Field {
+        key: key.to_string(),
+        op: if op == Cmp::Ne { Cmp::Eq } else { op },
+        value,
+    });
+    Ok(match op {
+        Cmp::Ne => field.not(),
+        _ => field,
+    })
+}
+
+/// Whether `key` is one of the file attributes of a metadata, which are not
+/// kept with its fields
+pub fn is_attribute(key: &str) -> bool {
+    matches!(key, "filename" | "mime_type" | "size" | "created")
+}
+
+/// Splits `key<op>value` at the first comparison operator outside quotes.
+fn split_comparison(word: &str) -> Option<(&str, Cmp, &str)> {
+    let mut quoted = false;
+    for (i, c) in word.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '=' | '!' | '<' | '>' if !quoted => {
+                let rest = &word[i..];
+                for (symbol, op) in [
+                    ("!=", Cmp::Ne),
+                    ("<=", Cmp::Le),
+                    (">=", Cmp::Ge),
+                    ("=", Cmp::Eq),
+                    ("<", Cmp::Lt),
+                    (">", Cmp::Gt),
+                ] {
+                    if let Some(value) = rest.strip_prefix(symbol) {
+                        return Some((&word[..i], op, value));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn unquote(word: &str) -> String {
+    word.replace('"', "")
+}
+
+/// Parses a size in bytes, with an optional unit such as `10MB` or `4KiB`.
+pub fn parse_size(value: &str) -> Result<u64, Error> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000 * 1000,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "t" | "tb" => 1000 * 1000 * 1000 * 1000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(invalid(&format!("invalid size {:?}", value))),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| invalid(&format!("invalid size {:?}", value)))
+}
+
+/// Parses a date as seconds since the Unix epoch, either `YYYY-MM-DD`,
+/// `YYYY-MM-DDTHH:MM:SS` with an optional `Z`, in UTC, or seconds.
+pub fn parse_date(value: &str) -> Result<u64, Error> {
+    if let Ok(secs) = value.parse::<u64>() {
+        return Ok(secs);
+    }
+
+    let error = || invalid(&format!("invalid date {:?}", value));
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, Some(time.trim_end_matches('Z'))),
+        None => (value, None),
+    };
+
+    let parts: Vec<&str> = date.split('-').collect();
+    let (year, month, day) = match parts.as_slice() {
+        [y, m, d] if y.len() == 4 => (
+            y.parse::<i64>().map_err(|_| error())?,
+            m.parse::<i64>().map_err(|_| error())?,
+            d.parse::<i64>().map_err(|_| error())?,
+        ),
+        _ => return Err(error()),
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(error());
+    }
+
+    let mut secs = days_from_civil(year, month, day) * 86400;
+    if let Some(time) = time {
+        let parts: Vec<i64> = time
+            .split(':')
+            .map(|p| p.parse::<i64>().map_err(|_| error()))
+            .collect::<Result<_, _>>()?;
+        match parts.as_slice() {
+            [h, m, s] if *h < 24 && *m < 60 && *s < 60 => secs += h * 3600 + m * 60 + s,
+            [h, m] if *h < 24 && *m < 60 => secs += h * 3600 + m * 60,
+            _ => return Err(error()),
+        }
+    }
+
+    u64::try_from(secs).map_err(|_| error())
+}
+
+/// Number of days since 1970-01-01 of a date in the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidArgument(format!("Invalid query: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(tag: &str) -> Query {
+        Query::Term(Term::Tag(tag.to_string()))
+    }
+
+    fn field(key: &str, op: Cmp, value: &str) -> Query {
+        Query::Term(Term::Field {
+            key: key.to_string(),
+            op,
+            value: value.to_string(),
+        })
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Query::parse("").unwrap(), Query::All);
+        assert_eq!(Query::parse("invoice").unwrap(), tag("invoice"));
+
+        // AND binds tighter than OR, and is implicit between terms
+        assert_eq!(
+            Query::parse("invoice 2026 OR receipt").unwrap(),
+            Query::parse("(invoice AND 2026) or receipt").unwrap()
+        );
+        assert_eq!(
+            Query::parse("invoice AND (2026 OR receipt) NOT draft").unwrap(),
+            tag("invoice")
+                .and(tag("2026").or(tag("receipt")))
+                .and(tag("draft").not())
+        );
+
+        assert_eq!(
+            Query::parse("author=alice").unwrap(),
+            field("author", Cmp::Eq, "alice")
+        );
+        assert_eq!(
+            Query::parse("author!=alice").unwrap(),
+            field("author", Cmp::Eq, "alice").not()
+        );
+        assert_eq!(
+            Query::parse("year>=2020").unwrap(),
+            field("year", Cmp::Ge, "2020")
+        );
+        assert_eq!(
+            Query::parse("size>10MB").unwrap(),
+            field("size", Cmp::Gt, "10MB")
+        );
+        assert_eq!(
+            Query::parse("name=*.pdf mime=image/*").unwrap(),
+            field("filename", Cmp::Eq, "*.pdf").and(field("mime_type", Cmp::Eq, "image/*"))
+        );
+
+        // quoted terms are literal tags
+        assert_eq!(Query::parse("\"tax return\"").unwrap(), tag("tax return"));
+        assert_eq!(Query::parse("\"a>b*\"").unwrap(), tag("a>b\\*"));
+        assert_eq!(
+            Query::parse("author=\"alice smith*\"").unwrap(),
+            field("author", Cmp::Eq, "alice smith\\*")
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        for query in [
+            "(invoice",
+            "invoice)",
+            "invoice AND",
+            "NOT",
+            "\"unterminated",
+            "size>lots",
+            "size>",
+            "created<yesterday",
+            "=value",
+        ] {
+            assert!(
+                matches!(Query::parse(query), Err(Error::InvalidArgument(_))),
+                "{:?} should not parse",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn eval() {
+        let tags = ["invoice", "2026"];
+        let has_tag = |t: &Term| match t {
+            Term::Tag(pattern) => Ok(tags.iter().any(|tag| glob_match(pattern, tag))),
+            _ => Ok(false),
+        };
+
+        for (query, expected) in [
+            ("invoice", true),
+            ("invoice receipt", false),
+            ("invoice OR receipt", true),
+            ("invoice NOT draft", true),
+            ("NOT (invoice OR receipt)", false),
+            ("inv*", true),
+            ("20??", true),
+            ("", true),
+        ] {
+            assert_eq!(
+                Query::parse(query).unwrap().eval(&has_tag).unwrap(),
+                expected,
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("invoice", "invoice"));
+        assert!(!glob_match("invoice", "invoices"));
+        assert!(glob_match("inv*", "invoice"));
+        assert!(glob_match("*.pdf", "report.2026.pdf"));
+        assert!(!glob_match("*.pdf", "report.pdf.gz"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("?at", "cat"));
+        assert!(!glob_match("?at", "at"));
+        assert!(glob_match("*", ""));
+
+        let literal = escape_glob("what?*");
+        assert!(glob_match(&literal, "what?*"));
+        assert!(!glob_match(&literal, "whatever"));
+        assert!(is_literal(&escape_glob("what?*")));
+        assert!(!is_literal("inv*"));
+        assert_eq!(unescape_glob(&escape_glob("a\\b*")), "a\\b*");
+    }
+
+    #[test]
+    fn tag_tokens() {
+        let search_key = &Key::from(b"a search key, not the data key..");
+        let query = Query::parse("invoice tags=2026 author=alice").unwrap();
+        assert_eq!(
+            query.tag_tokens(search_key).unwrap(),
+            Query::Term(Term::Tag(tag_token(search_key, "invoice")))
+                .and(Query::Term(Term::Tag(tag_token(search_key, "2026"))))
+                .and(Query::Term(Term::Tag(tag_token(
+                    search_key,
+                    "author=alice"
+                ))))
+        );
+
+        for query in ["inv*", "year>2020", "author=al*", "size=10", "name=a.pdf"] {
+            let query = Query::parse(query).unwrap();
+            assert!(matches!(
+                query.tag_tokens(search_key),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn sizes_and_dates() {
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("10MB").unwrap(), 10_000_000);
+        assert_eq!(parse_size("4KiB").unwrap(), 4096);
+        assert!(parse_size("10XB").is_err());
+
+        assert_eq!(parse_date("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_date("2000-03-01").unwrap(), 951868800);
+        assert_eq!(parse_date("2026-01-01T12:30:00Z").unwrap(), 1767270600);
+        assert_eq!(parse_date("1767270600").unwrap(), 1767270600);
+        assert!(parse_date("2026-13-01").is_err());
+    }
+}
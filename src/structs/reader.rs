@@ -0,0 +1,314 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use super::{fetch_block, seek_position, Manifest, Pointer};
+use crate::backend::Backend;
+use crate::crypto::{blocks_key, Key};
+use crate::error::Error;
+
+/// PointerReader streams the plaintext of an encrypted pointer stored in a
+/// backend. Blocks are fetched and decrypted lazily, one at a time, so that
+/// only a single block is kept in memory regardless of the size of the data.
+/// Each block is checked against its CID in the pointer manifest when it is
+/// loaded.
+pub struct PointerReader<'a> {
+    pointer: Pointer,
+    manifest: Manifest,
+    /// Key the blocks are encrypted with, derived from the data key
+    blocks_key: Key,
+    stream_id: Vec<u8>,
+    backend: &'a dyn Backend,
+    /// Offset of the first byte of each block
+    offsets: Vec<u64>,
+    len: u64,
+    pos: u64,
+    /// Index and plaintext of the last block read
+    current: Option<(usize, Vec<u8>)>,
+}
+
+impl<'a> PointerReader<'a> {
+    /// Creates a reader over an encrypted pointer, usually loaded with
+    /// `Pointer::load_header`. The pointer manifest is decrypted upfront, so
+    /// that a wrong key is detected before any block is fetched.
+    pub fn new(
+        pointer: Pointer,
+        key: &Key,
+        backend: &'a dyn Backend,
+    ) -> Result<PointerReader<'a>, Error> {
+        let mut header = pointer;
+        header.0.blocks = vec![];
+        let manifest = header.open_manifest(key)?;
+        let stream_id = header.0.stream_id.clone();
+        let pointer = header.into_plaintext(manifest.clone(), vec![]);
+
+        let mut offsets = vec![];
+        let mut len: u64 = 0;
+        for l in &pointer.0.lengths {
+            offsets.push(len);
+            len += l;
+        }
+
+        Ok(PointerReader {
+            pointer,
+            manifest,
+            blocks_key: blocks_key(key),
+            stream_id,
+            backend,
+            offsets,
+            len,
+            pos: 0,
+            current: None,
+        })
+    }
+
+    /// Returns the decrypted pointer header
+    pub fn pointer(&self) -> &Pointer {
+        &self.pointer
+    }
+
+    /// Total size in bytes of the plaintext
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Fetches and decrypts the block at `idx`, unless it is the current one.
+    fn load_block(&mut self, idx: usize) -> Result<(), Error> {
+        if let Some((current_idx, _)) = &self.current {
+            if *current_idx == idx {
+                return Ok(());
+            }
+        }
+
+        let link = &self.pointer.0.links[idx];
+        let block = fetch_block(link, self.backend)?;
+        let block = self.manifest.open_block(
+            self.pointer.0.cipher,
+            block,
+            &self.blocks_key,
+            &self.stream_id,
+            idx,
+        )?;
+
+        if block.data.len() as u64 != self.pointer.0.lengths[idx] {
+            return Err(Error::CorruptBlock(format!(
+                "Block {} does not have the expected length",
+                link
+            )));
+        }
+
+        self.current = Some((idx, block.data.into_vec()));
+        Ok(())
+    }
+}
+
+impl Read for PointerReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        // index of the block which contains the current position
+        let idx = match self.offsets.binary_search(&self.pos) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        // skips over empty blocks
+        let idx = (idx..self.offsets.len())
+            .find(|i| self.pointer.0.lengths[*i] > 0)
+            .unwrap_or(idx);
+        self.load_block(idx)?;
+
+        let data = match &self.current {
+            Some((_, data)) => data,
+            None => return Ok(0),
+        };
+        let start = (self.pos - self.offsets[idx]) as usize;
+        let n = std::cmp::min(buf.len(), data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl Seek for PointerReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, std::io::Error> {
+        self.pos = seek_position(self.pos, self.len, pos)?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::structs::{cid_of, LEGACY_BLOCK_SIZE};
+    use std::cell::Cell;
+    use std::io::BufReader;
+
+    /// Backend wrapper which counts how many objects are fetched
+    struct CountingBackend {
+        inner: MemoryBackend,
+        gets: Cell<usize>,
+    }
+
+    impl Backend for CountingBackend {
+        fn put(&mut self, cid: &str, data: &[u8]) -> Result<(), Error> {
+            self.inner.put(cid, data)
+        }
+        fn get(&self, cid: &str) -> Result<Option<Vec<u8>>, Error> {
+            self.gets.set(self.gets.get() + 1);
+            self.inner.get(cid)
+        }
+        fn has(&self, cid: &str) -> Result<bool, Error> {
+            self.inner.has(cid)
+        }
+        fn delete(&mut self, cid: &str) -> Result<(), Error> {
+            self.inner.delete(cid)
+        }
+        fn list(&self) -> Result<Vec<String>, Error> {
+            self.inner.list()
+        }
+        fn set_ref(&mut self, name: &str, cid: Option<&str>) -> Result<(), Error> {
+            self.inner.set_ref(name, cid)
+        }
+        fn get_ref(&self, name: &str) -> Result<Option<String>, Error> {
+            self.inner.get_ref(name)
+        }
+    }
+
+    fn stored_pointer(data: &[u8], key: &Key) -> (String, CountingBackend) {
+        let mut backend = CountingBackend {
+            inner: MemoryBackend::new(),
+            gets: Cell::new(0),
+        };
+        let mut pointer = Pointer::from_with_block_size(data, LEGACY_BLOCK_SIZE)
+            .unwrap()
+            .encrypt(key)
+            .unwrap();
+        let root = pointer.store(&mut backend).unwrap();
+
+        (root, backend)
+    }
+
+    #[test]
+    fn streaming_read() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 4 + 10).map(|i| i as u8).collect();
+        let (root, backend) = stored_pointer(&data, key);
+
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        assert_eq!(backend.gets.get(), 1);
+
+        let reader = header.reader(key, &backend).unwrap();
+        assert_eq!(reader.len(), data.len() as u64);
+        // no block is fetched until data is read
+        assert_eq!(backend.gets.get(), 1);
+
+        let mut out = vec![];
+        std::io::copy(&mut BufReader::new(reader), &mut out).unwrap();
+        assert_eq!(out, data);
+        assert_eq!(backend.gets.get(), 1 + 5);
+    }
+
+    #[test]
+    fn streaming_seek() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 4 + 10).map(|i| i as u8).collect();
+        let (root, backend) = stored_pointer(&data, key);
+
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        let mut reader = header.reader(key, &backend).unwrap();
+
+        // seeking to the last block only fetches that block
+        let pos = (LEGACY_BLOCK_SIZE * 4 + 5) as u64;
+        reader.seek(SeekFrom::Start(pos)).unwrap();
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data[pos as usize..].to_vec());
+        assert_eq!(backend.gets.get(), 1 + 1);
+
+        // reads across a block boundary
+        reader
+            .seek(SeekFrom::Start(LEGACY_BLOCK_SIZE as u64 - 2))
+            .unwrap();
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[LEGACY_BLOCK_SIZE - 2..LEGACY_BLOCK_SIZE + 2]);
+
+        // reading past the end returns no data
+        reader.seek(SeekFrom::End(10)).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn wrong_key() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let (root, backend) = stored_pointer(&[1, 2, 3], key);
+
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        assert!(header
+            .reader(&Key::from(b"another very very secret key...."), &backend)
+            .is_err());
+    }
+
+    #[test]
+    fn verify_integrity() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let (root, backend) = stored_pointer(&data, key);
+
+        // a pointer CID which does not match its manifest is detected before
+        // any block is read
+        let mut header = Pointer::load_header(&root, &backend).unwrap();
+        let cid = cid_of(b"another file").unwrap();
+        header.0.cid = cid.clone();
+        match header.reader(key, &backend) {
+            Err(Error::IntegrityMismatch(c)) => assert_eq!(c, cid),
+            r => panic!("unexpected result {:?}", r.map(|r| r.len())),
+        }
+
+        // a block which claims the CID of another block is detected
+        let mut pointer = Pointer::load(&root, &backend).unwrap();
+        let block_cid = pointer.0.blocks[0].cid.clone();
+        pointer.0.blocks[1].cid = block_cid.clone();
+        match pointer.decrypt(key) {
+            Err(Error::IntegrityMismatch(c)) => assert_eq!(c, block_cid),
+            r => panic!("unexpected result {:?}", r.map(|p| p.cid().to_string())),
+        }
+    }
+
+    #[test]
+    fn reordered_links() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let data: Vec<u8> = (0..LEGACY_BLOCK_SIZE * 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let (root, backend) = stored_pointer(&data, key);
+
+        let mut header = Pointer::load_header(&root, &backend).unwrap();
+        header.0.links.swap(0, 1);
+        let mut reader = header.reader(key, &backend).unwrap();
+        let mut out = vec![];
+        let res = reader.read_to_end(&mut out).map_err(Error::from);
+        assert!(matches!(res, Err(Error::Authentication(_))));
+    }
+
+    #[test]
+    fn empty_pointer() {
+        let key = &Key::from(b"hello darkness my good ol friend");
+        let (root, backend) = stored_pointer(&[], key);
+
+        let header = Pointer::load_header(&root, &backend).unwrap();
+        let mut reader = header.reader(key, &backend).unwrap();
+        assert!(reader.is_empty());
+
+        let mut out = vec![];
+        assert_eq!(reader.read_to_end(&mut out).unwrap(), 0);
+    }
+}
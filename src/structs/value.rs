@@ -0,0 +1,347 @@
+use serde_cbor::Value as Cbor;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use super::query::{glob_match, parse_date, parse_size, Cmp};
+use super::Tags;
+use crate::error::Error;
+
+/// Fields of a metadata, by name
+pub type Fields = BTreeMap<String, Value>;
+
+/// Name of the field which holds the plain tags of a metadata
+pub const TAGS_FIELD: &str = "tags";
+
+/// Key of the single entry map a timestamp is encoded as, since DAG-CBOR does
+/// not allow CBOR tags other than CIDs
+const TIMESTAMP_KEY: &str = "timestamp";
+
+/// Value of a metadata field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    /// Seconds since the Unix epoch
+    Timestamp(u64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// Whether the value compares to `operand` as `op`, with the operand read
+    /// as the type of the value: a glob pattern for strings, an integer with
+    /// an optional size unit, a date, or hex encoded bytes. Strings and bytes
+    /// are otherwise ordered lexically. A list matches if any of its values
+    /// does, and an operand which is not of the type of the value never
+    /// matches.
+    pub fn compare(&self, op: Cmp, operand: &str) -> bool {
+        if op == Cmp::Ne {
+            return !self.compare(Cmp::Eq, operand);
+        }
+
+        match self {
+            Value::String(s) if op == Cmp::Eq => glob_match(operand, s),
+            Value::String(s) => op.eval(s.as_str().cmp(operand)),
+            Value::Integer(i) => match operand.parse::<i64>() {
+                Ok(n) => op.eval(i.cmp(&n)),
+                Err(_) => {
+                    parse_size(operand).is_ok_and(|n| op.eval(i128::from(*i).cmp(&i128::from(n))))
+                }
+            },
+            Value::Timestamp(t) => parse_date(operand).is_ok_and(|d| op.eval(t.cmp(&d))),
+            Value::Bytes(b) => from_hex(operand).is_some_and(|o| op.eval(b.cmp(&o))),
+            Value::List(values) => values.iter().any(|v| v.compare(op, operand)),
+        }
+    }
+
+    /// Parses the value of a `key=value` tag: an integer, a timestamp if the
+    /// value is a date such as `YYYY-MM-DD`, or else a string.
+    pub(super) fn parse(value: &str) -> Value {
+        match value.parse::<i64>() {
+            Ok(i) => Value::Integer(i),
+            Err(_) if value.contains('-') => match parse_date(value) {
+                Ok(t) => Value::Timestamp(t),
+                Err(_) => Value::from(value),
+            },
+            Err(_) => Value::from(value),
+        }
+    }
+
+    /// Canonical text the value is matched with by tag tokens: timestamps as
+    /// seconds since the epoch and bytes hex encoded, as `compare` reads them.
+    /// See `Metadata::tag_tokens`.
+    pub(super) fn token_text(&self) -> Vec<String> {
+        match self {
+            Value::String(s) => vec![s.clone()],
+            Value::Integer(i) => vec![i.to_string()],
+            Value::Timestamp(t) => vec![t.to_string()],
+            Value::Bytes(b) => vec![b.iter().map(|b| format!("{:02x}", b)).collect()],
+            Value::List(values) => values.iter().flat_map(|v| v.token_text()).collect(),
+        }
+    }
+
+    fn to_cbor(&self) -> Cbor {
+        match self {
+            Value::String(s) => Cbor::Text(s.clone()),
+            Value::Integer(i) => Cbor::Integer(i128::from(*i)),
+            Value::Timestamp(t) => {
+                let mut map = BTreeMap::new();
+                map.insert(
+                    Cbor::Text(TIMESTAMP_KEY.to_string()),
+                    Cbor::Integer(i128::from(*t)),
+                );
+                Cbor::Map(map)
+            }
+            Value::Bytes(b) => Cbor::Bytes(b.clone()),
+            Value::List(values) => Cbor::Array(values.iter().map(|v| v.to_cbor()).collect()),
+        }
+    }
+
+    fn from_cbor(value: Cbor) -> Result<Value, Error> {
+        let error = || Error::Decode("Unsupported metadata field value".to_string());
+        match value {
+            Cbor::Text(s) => Ok(Value::String(s)),
+            Cbor::Integer(i) => i64::try_from(i).map(Value::Integer).map_err(|_| error()),
+            Cbor::Bytes(b) => Ok(Value::Bytes(b)),
+            Cbor::Array(values) => values
+                .into_iter()
+                .map(Value::from_cbor)
+                .collect::<Result<_, _>>()
+                .map(Value::List),
+            Cbor::Map(map) if map.len() == 1 => match map.into_iter().next() {
+                Some((Cbor::Text(k), Cbor::Integer(t))) if k == TIMESTAMP_KEY => {
+                    u64::try_from(t).map(Value::Timestamp).map_err(|_| error())
+                }
+                _ => Err(error()),
+            },
+            _ => Err(error()),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        Value::Integer(i)
+    }
+}
+
+impl Tags {
+    /// Converts the tags to metadata fields. Plain tags are kept in the `tags`
+    /// list, and `key=value` tags are set as fields: an integer, a timestamp
+    /// if the value is a `YYYY-MM-DD` date, or else a string. A `tags=value`
+    /// tag is the plain tag `value`, as it is queried. Keys repeated across
+    /// tags are set to the list of their values.
+    pub fn into_fields(self) -> Fields {
+        let mut fields = Fields::new();
+        let mut tags = vec![];
+        for tag in self.values {
+            let (key, value) = match tag.split_once('=') {
+                Some((TAGS_FIELD, value)) => {
+                    tags.push(Value::from(value));
+                    continue;
+                }
+                Some((key, value)) if !key.is_empty() => (key, Value::parse(value)),
+                _ => {
+                    tags.push(Value::String(tag));
+                    continue;
+                }
+            };
+
+            match fields.remove(key) {
+                None => fields.insert(key.to_string(), value),
+                Some(Value::List(mut values)) => {
+                    values.push(value);
+                    fields.insert(key.to_string(), Value::List(values))
+                }
+                Some(previous) => {
+                    fields.insert(key.to_string(), Value::List(vec![previous, value]))
+                }
+            };
+        }
+
+        if !tags.is_empty() {
+            fields.insert(TAGS_FIELD.to_string(), Value::List(tags));
+        }
+        fields
+    }
+}
+
+/// Encodes fields as canonical DAG-CBOR: map keys are sorted by length then
+/// bytes and integers take their shortest form, so that equal fields always
+/// have the same encoding, whatever order they were set in.
+pub fn encode_fields(fields: &Fields) -> Result<Vec<u8>, Error> {
+    let map = fields
+        .iter()
+        .map(|(k, v)| (Cbor::Text(k.clone()), v.to_cbor()))
+        .collect();
+    serde_cbor::to_vec(&Cbor::Map(map)).map_err(|e| Error::Encode(e.to_string()))
+}
+
+/// Decodes fields encoded with `encode_fields`. Fields encoded as JSON tags,
+/// as metadata was encoded before it was typed, are read as tags.
+pub fn decode_fields(raw: &[u8]) -> Result<Fields, Error> {
+    if raw.first() == Some(&b'{') {
+        let tags: Tags = serde_json::from_slice(raw)
+            .map_err(|e| Error::Decode(format!("Error decoding metadata tags: {}", e)))?;
+        return Ok(tags.into_fields());
+    }
+
+    let map = match serde_cbor::from_slice(raw) {
+        Ok(Cbor::Map(map)) => map,
+        Ok(_) => return Err(Error::Decode("Metadata fields are not a map".to_string())),
+        Err(e) => {
+            return Err(Error::Decode(format!(
+                "Error decoding metadata fields: {}",
+                e
+            )))
+        }
+    };
+
+    let mut fields = Fields::new();
+    for (key, value) in map {
+        match key {
+            Cbor::Text(key) => fields.insert(key, Value::from_cbor(value)?),
+            _ => {
+                return Err(Error::Decode(
+                    "Metadata field name is not a string".to_string(),
+                ))
+            }
+        };
+    }
+    Ok(fields)
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> Fields {
+        let mut fields = Fields::new();
+        fields.insert("author".to_string(), Value::from("alice"));
+        fields.insert("year".to_string(), Value::Integer(2026));
+        fields.insert("due".to_string(), Value::Timestamp(1767225600));
+        fields.insert("digest".to_string(), Value::Bytes(vec![0xca, 0xfe]));
+        fields.insert(
+            TAGS_FIELD.to_string(),
+            Value::List(vec![Value::from("invoice"), Value::Integer(-1)]),
+        );
+        fields
+    }
+
+    #[test]
+    fn canonical_encoding() {
+        let raw = encode_fields(&fields()).unwrap();
+        assert_eq!(decode_fields(&raw).unwrap(), fields());
+
+        // keys are sorted by length first, whatever the insertion order
+        let mut reversed = Fields::new();
+        for (k, v) in fields().into_iter().rev() {
+            reversed.insert(k, v);
+        }
+        assert_eq!(encode_fields(&reversed).unwrap(), raw);
+        let keys: Vec<String> = match serde_cbor::from_slice(&raw).unwrap() {
+            Cbor::Map(map) => map
+                .into_keys()
+                .map(|k| match k {
+                    Cbor::Text(k) => k,
+                    _ => unreachable!(),
+                })
+                .collect(),
+            _ => unreachable!(),
+        };
+        assert_eq!(keys, vec!["due", "tags", "year", "author", "digest"]);
+
+        // a single field with an integer, in its shortest form
+        let mut small = Fields::new();
+        small.insert("n".to_string(), Value::Integer(1));
+        assert_eq!(encode_fields(&small).unwrap(), vec![0xa1, 0x61, b'n', 0x01]);
+
+        assert!(decode_fields(&serde_cbor::to_vec(&Cbor::Float(1.0)).unwrap()).is_err());
+        assert!(decode_fields(&[0xff]).is_err());
+    }
+
+    #[test]
+    fn legacy_tags() {
+        let raw = br#"{"values":["invoice","author=alice"]}"#;
+        let fields = decode_fields(raw).unwrap();
+        assert_eq!(fields["author"], Value::from("alice"));
+        assert_eq!(
+            fields[TAGS_FIELD],
+            Value::List(vec![Value::from("invoice")])
+        );
+    }
+
+    #[test]
+    fn tags_into_fields() {
+        let tags = Tags {
+            values: vec![
+                "invoice".to_string(),
+                "author=alice".to_string(),
+                "author=bob".to_string(),
+                "year=2026".to_string(),
+                "due=2026-01-01".to_string(),
+                "=odd".to_string(),
+                "tags=draft".to_string(),
+            ],
+        };
+        let fields = tags.into_fields();
+        assert_eq!(
+            fields[TAGS_FIELD],
+            Value::List(vec![
+                Value::from("invoice"),
+                Value::from("=odd"),
+                Value::from("draft")
+            ])
+        );
+        assert_eq!(
+            fields["author"],
+            Value::List(vec![Value::from("alice"), Value::from("bob")])
+        );
+        assert_eq!(fields["year"], Value::Integer(2026));
+        assert_eq!(fields["due"], Value::Timestamp(1767225600));
+    }
+
+    #[test]
+    fn compare() {
+        let string = Value::from("alice");
+        assert!(string.compare(Cmp::Eq, "al*"));
+        assert!(string.compare(Cmp::Ne, "bob"));
+        assert!(string.compare(Cmp::Lt, "bob"));
+
+        let integer = Value::Integer(2_000_000);
+        assert!(integer.compare(Cmp::Gt, "1MB"));
+        assert!(integer.compare(Cmp::Le, "2000000"));
+        assert!(!integer.compare(Cmp::Eq, "alice"));
+        assert!(Value::Integer(-5).compare(Cmp::Lt, "0"));
+
+        let timestamp = Value::Timestamp(1767225600);
+        assert!(timestamp.compare(Cmp::Ge, "2026-01-01"));
+        assert!(timestamp.compare(Cmp::Lt, "2026-01-01T00:00:01"));
+        assert!(!timestamp.compare(Cmp::Eq, "soon"));
+
+        let bytes = Value::Bytes(vec![0xca, 0xfe]);
+        assert!(bytes.compare(Cmp::Eq, "cafe"));
+        assert!(!bytes.compare(Cmp::Eq, "caf"));
+
+        let list = Value::List(vec![Value::from("a"), Value::Integer(3)]);
+        assert!(list.compare(Cmp::Eq, "a"));
+        assert!(list.compare(Cmp::Gt, "2"));
+        assert!(!list.compare(Cmp::Ne, "a"));
+    }
+}
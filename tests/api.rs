@@ -0,0 +1,103 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use en_crypt::{
+    Backend, Block, Chunker, Error, FsBackend, KdfParams, Key, Match, MemoryBackend, Metadata,
+    Pointer, PointerBuilder, Tags,
+};
+
+const KEY: &Key = &Key::new(*b"hello darkness my good ol friend");
+
+fn test_data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+#[test]
+fn store_and_read_pointer() {
+    let data = test_data(10_000);
+    let mut backend = MemoryBackend::new();
+
+    let mut pointer = Pointer::from_with_block_size(&data, 1024)
+        .unwrap()
+        .encrypt(KEY)
+        .unwrap();
+    let root = pointer.store(&mut backend).unwrap();
+
+    let header = Pointer::load_header(&root, &backend).unwrap();
+    assert_eq!(header.blocks_len(), 10);
+
+    let mut reader = header.reader(KEY, &backend).unwrap();
+    assert_eq!(reader.len(), data.len() as u64);
+    reader.seek(SeekFrom::Start(5000)).unwrap();
+    let mut out = vec![];
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, data[5000..].to_vec());
+}
+
+#[test]
+fn build_with_passphrase_in_fs_backend() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut backend = FsBackend::new(dir.path()).unwrap();
+    let data = test_data(100_000);
+
+    let mut builder = PointerBuilder::with_passphrase(
+        "passphrase",
+        KdfParams::new(64, 1, 1),
+        Chunker::fast_cdc(4096),
+        &mut backend,
+    )
+    .unwrap();
+    builder.write_all(&data).unwrap();
+    let root = builder.finish().unwrap();
+    assert!(backend.has(&root).unwrap());
+
+    let header = Pointer::load_header(&root, &backend).unwrap();
+    let key = header.derive_key("passphrase").unwrap();
+    let mut out = vec![];
+    header
+        .clone()
+        .reader(&key, &backend)
+        .unwrap()
+        .read_to_end(&mut out)
+        .unwrap();
+    assert_eq!(out, data);
+
+    // the data key is wrapped, so a wrong passphrase fails to unwrap it
+    assert!(matches!(
+        header.derive_key("another passphrase"),
+        Err(Error::WrongKey)
+    ));
+    let wrong_key = Key::from([0; 32]);
+    assert!(matches!(
+        header.reader(&wrong_key, &backend),
+        Err(Error::WrongKey)
+    ));
+}
+
+#[test]
+fn block_encryption() {
+    let mut block = Block::new_empty();
+    block.write_all(&[1, 2, 3, 4]).unwrap();
+
+    let encrypted = block.clone().encrypt(KEY, b"associated data").unwrap();
+    assert_ne!(encrypted.as_raw(), block.as_raw());
+
+    assert!(matches!(
+        encrypted.clone().decrypt(KEY, b"other data"),
+        Err(Error::Authentication(_))
+    ));
+    let decrypted = encrypted.decrypt(KEY, b"associated data").unwrap();
+    assert_eq!(decrypted.as_raw(), &[1, 2, 3, 4]);
+    assert_eq!(decrypted.cid, block.cid);
+}
+
+#[test]
+fn metadata_tags() {
+    let tags = Tags {
+        values: vec!["invoice".to_string(), "2021".to_string()],
+    };
+    let metadata = Metadata::new(tags).unwrap().encrypt(KEY).unwrap();
+
+    let metadata = metadata.decrypt(KEY).unwrap();
+    assert!(metadata.query("invoice".to_string()).unwrap());
+    assert!(!metadata.query("receipt".to_string()).unwrap());
+}